@@ -0,0 +1,13 @@
+#version 300 es
+precision highp float;
+
+// positions are already in clip space, see renderer::DebugDraw
+in vec4 vertex;
+in vec4 color;
+
+out vec4 vertex_color;
+
+void main() {
+    vertex_color = color;
+    gl_Position = vertex;
+}
@@ -0,0 +1,74 @@
+#version 300 es
+precision highp float;
+
+uniform sampler2D texture_color;
+// false for plain colored rectangles
+uniform bool textured;
+
+in vec2 vertex_texcoord;
+in vec4 vertex_color;
+in vec2 vertex_hue;
+
+out vec4 frag_color;
+
+vec3 rgb_to_hsl(vec3 rgb) {
+    vec3 ret;
+    float min = min(min(rgb.r, rgb.g), rgb.b);
+    float max = max(max(rgb.r, rgb.g), rgb.b);
+    float lum = (max + min) / 2.0;
+    ret.z = lum;
+    if (max == min) {
+        ret.x = ret.y = 0.0;
+    } else {
+        float chroma = max - min;
+        ret.y = chroma / (1.0 - abs(2.0 * lum - 1.0));
+        if (max == rgb.r) {
+            ret.x = (rgb.g - rgb.b) / chroma + (rgb.g < rgb.b ? 6.0 : 0.0);
+        } else if (max == rgb.g) {
+            ret.x = (rgb.b - rgb.r) / chroma + 2.0;
+        } else {
+            ret.x = (rgb.r - rgb.g) / chroma + 4.0;
+        }
+        ret.x /= 6.0;
+    }
+    return ret;
+}
+
+float hue_to_rgb(float p, float q, float t) {
+    if (t < 0.0) t += 1.0;
+    if (t > 1.0) t -= 1.0;
+    if (t < 1.0/6.0) return p + (q - p) * 6.0 * t;
+    if (t < 1.0/2.0) return q;
+    if (t < 2.0/3.0) return p + (q - p) * (2.0/3.0 - t) * 6.0;
+    return p;
+}
+
+vec3 hsl_to_rgb(vec3 hsl) {
+    vec3 ret;
+    if (hsl.y == 0.0) {
+        ret.r = ret.g = ret.b = hsl.z;
+    } else {
+        float q = hsl.z < 0.5 ? hsl.z * (1.0 + hsl.y) : hsl.z + hsl.y - hsl.z * hsl.y;
+        float p = 2.0 * hsl.z - q;
+        ret.r = hue_to_rgb(p, q, hsl.x + 1.0/3.0);
+        ret.g = hue_to_rgb(p, q, hsl.x);
+        ret.b = hue_to_rgb(p, q, hsl.x - 1.0/3.0);
+    }
+    return ret;
+}
+
+void main() {
+    frag_color = vec4(1.0);
+    if (textured) {
+        frag_color = texture(texture_color, vertex_texcoord);
+    }
+    if (vertex_hue != vec2(1.0, 0.0)) {
+        vec3 hsl = rgb_to_hsl(frag_color.rgb);
+        hsl.x = mod(hsl.x * vertex_hue.x + vertex_hue.y, 1.0);
+        frag_color.rgb = hsl_to_rgb(hsl);
+    }
+    frag_color *= vertex_color;
+    if (frag_color.a == 0.0) {
+        discard;
+    }
+}
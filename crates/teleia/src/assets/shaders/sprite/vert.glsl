@@ -0,0 +1,23 @@
+#version 300 es
+precision highp float;
+
+// positions are in pixels relative to the center of the screen, y up, see renderer::SpriteBatch
+in vec2 vertex;
+in vec2 texcoord;
+in vec4 color;
+// (scale, shift) applied to the hue, (1, 0) leaves it unchanged
+layout(location = 6) in vec2 hue;
+
+uniform mat4 view;
+uniform mat4 projection;
+
+out vec2 vertex_texcoord;
+out vec4 vertex_color;
+out vec2 vertex_hue;
+
+void main() {
+    vertex_texcoord = texcoord;
+    vertex_color = color;
+    vertex_hue = hue;
+    gl_Position = projection * view * vec4(vertex, 0.0, 1.0);
+}
@@ -3,12 +3,15 @@ precision highp float;
 
 in vec2 vertex;
 in vec2 texcoord;
+// index into animation_offsets, 0 for tiles that aren't animated
+layout(location = 6) in float animation;
 
 uniform mat4 transform;
+uniform vec2 animation_offsets[64];
 
 out vec2 vertex_texcoord;
 
 void main() {
-    vertex_texcoord = texcoord;
+    vertex_texcoord = texcoord + animation_offsets[int(animation + 0.5)];
     gl_Position = transform * vec4(vertex, 0.0, 1.0);
 }
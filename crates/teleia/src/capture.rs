@@ -0,0 +1,280 @@
+use crate::{context, framebuffer, utils};
+use crate::state::Tick;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(module = "/src/helpers.js")]
+extern {
+    fn js_download_bytes(name: &str, mime: &str, bytes: &[u8]);
+}
+
+/// Which image a screenshot is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The game's render framebuffer, at native resolution and before postprocessing
+    Native,
+    /// What ends up on screen, after postprocessing and upscaling
+    Output,
+}
+
+/// An RGBA8 image, top row first
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Read back an offscreen framebuffer (the screen has no texture, see State::screenshot)
+    pub fn from_framebuffer(ctx: &context::Context, fb: &framebuffer::Framebuffer) -> Self {
+        let (width, height) = (fb.dims.x as u32, fb.dims.y as u32);
+        let mut raw = vec![0; width as usize * height as usize * 4];
+        fb.get_pixels_raw(ctx, &mut raw);
+        // GL returns the bottom row first
+        let pixels = raw.chunks_exact(width as usize * 4).rev().flatten().copied().collect();
+        Self { width, height, pixels }
+    }
+
+    pub fn to_png(&self) -> utils::Erm<Vec<u8>> {
+        let mut ret = Vec::new();
+        image::ImageEncoder::write_image(
+            image::codecs::png::PngEncoder::new(&mut ret),
+            &self.pixels,
+            self.width, self.height,
+            image::ExtendedColorType::Rgba8,
+        )?;
+        Ok(ret)
+    }
+}
+
+pub const DEFAULT_GIF_SECONDS: f64 = 10.0;
+/// GIF delays are in hundredths of a second, so very high frame rates can't be represented
+pub const GIF_MAX_FPS: f64 = 30.0;
+
+/// Ring buffer of recent frames that can be written out as an animated GIF
+pub struct GifRecorder {
+    pub seconds: f64,
+    pub capacity: usize,
+    /// Capture one frame every this many ticks
+    pub interval: Tick,
+    frames: std::collections::VecDeque<Image>,
+    last_tick: Option<Tick>,
+}
+
+impl GifRecorder {
+    pub fn new(seconds: f64, tick_rate: f64) -> Self {
+        let interval = (tick_rate / GIF_MAX_FPS).ceil().max(1.0) as Tick;
+        let capacity = ((seconds * tick_rate) / interval as f64).ceil().max(1.0) as usize;
+        Self {
+            seconds,
+            capacity,
+            interval,
+            frames: std::collections::VecDeque::with_capacity(capacity),
+            last_tick: None,
+        }
+    }
+
+    pub fn len(&self) -> usize { self.frames.len() }
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+    pub fn clear(&mut self) { self.frames.clear(); }
+
+    pub fn capture(&mut self, ctx: &context::Context, fb: &framebuffer::Framebuffer, tick: Tick) {
+        if let Some(t) = self.last_tick && tick < t + self.interval { return; }
+        self.last_tick = Some(tick);
+        if self.frames.len() >= self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Image::from_framebuffer(ctx, fb));
+    }
+
+    /// Take the buffered frames to be encoded, leaving the buffer empty
+    pub fn take_clip(&mut self, tick_rate: f64) -> GifClip {
+        GifClip {
+            frames: self.frames.drain(..).collect(),
+            delay: image::Delay::from_numer_denom_ms((self.interval * 1000) as u32, tick_rate.round() as u32),
+        }
+    }
+}
+
+/// Frames taken from a GifRecorder. Encoding is slow, so this can be sent to another thread.
+pub struct GifClip {
+    pub frames: Vec<Image>,
+    pub delay: image::Delay,
+}
+
+impl GifClip {
+    /// Encode the frames as a looping GIF.
+    /// Each frame is reduced to a 256 color palette (exactly, if it has few enough colors).
+    pub fn encode(&self) -> utils::Erm<Vec<u8>> {
+        let mut ret = Vec::new();
+        {
+            let mut enc = image::codecs::gif::GifEncoder::new_with_speed(&mut ret, 10);
+            enc.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+            for img in self.frames.iter() {
+                let mut pixels = img.pixels.clone();
+                // GIF only has 1-bit transparency, and the framebuffer's alpha isn't meaningful here
+                for px in pixels.chunks_exact_mut(4) { px[3] = 255; }
+                let Some(buf) = image::RgbaImage::from_raw(img.width, img.height, pixels) else {
+                    return utils::erm_msg("frame has the wrong number of pixels");
+                };
+                enc.encode_frame(image::Frame::from_parts(buf, 0, 0, self.delay))?;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Offer the bytes as a file download
+#[cfg(target_arch = "wasm32")]
+pub fn export(_id: &str, name: &str, mime: &str, bytes: &[u8]) -> utils::Erm<()> {
+    js_download_bytes(name, mime, bytes);
+    Ok(())
+}
+
+/// Write the bytes to the captures directory under the game's data dir
+#[cfg(not(target_arch = "wasm32"))]
+pub fn export(id: &str, name: &str, _mime: &str, bytes: &[u8]) -> utils::Erm<()> {
+    let dir = captures_dir(id)?;
+    let path = dir.join(name);
+    std::fs::write(&path, bytes)?;
+    log::info!("saved {}", path.display());
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn captures_dir(id: &str) -> utils::Erm<std::path::PathBuf> {
+    let Some(pd) = directories::ProjectDirs::from("", "milkfat", id) else {
+        return utils::erm_msg("failed to get data directory");
+    };
+    let dir = pd.data_dir().join("captures");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// A file name that won't collide with earlier captures
+pub fn timestamped_name(prefix: &str, ext: &str) -> String {
+    #[cfg(target_arch = "wasm32")]
+    let ms = js_sys::Date::now() as u64;
+    #[cfg(not(target_arch = "wasm32"))]
+    let ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    format!("{}-{}.{}", prefix, ms, ext)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// Uncompressed YUV 4:4:4 in a single .y4m file (readable by ffmpeg, mpv, etc.)
+    Y4m,
+    /// A directory of numbered PNG files
+    PngSequence,
+}
+
+/// Frames that can be waiting for the video writer before capture blocks until it catches up
+#[cfg(not(target_arch = "wasm32"))]
+const VIDEO_QUEUE_FRAMES: usize = 8;
+
+/// Streams frames to disk from a background thread.
+/// Frames are captured once per tick, so the output plays back at the tick rate regardless of the display's refresh rate.
+/// If the disk can't keep up, the game slows down rather than buffering frames without limit.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct VideoRecorder {
+    pub path: std::path::PathBuf,
+    pub frames: u64,
+    last_tick: Option<Tick>,
+    tx: Option<std::sync::mpsc::SyncSender<(Image, u64)>>,
+    thread: Option<std::thread::JoinHandle<utils::Erm<()>>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl VideoRecorder {
+    pub fn start(path: std::path::PathBuf, format: VideoFormat, width: u32, height: u32, fps: u32) -> utils::Erm<Self> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(VIDEO_QUEUE_FRAMES);
+        let out = path.clone();
+        let thread = std::thread::spawn(move || match format {
+            VideoFormat::Y4m => write_y4m(&out, rx, width, height, fps),
+            VideoFormat::PngSequence => write_png_sequence(&out, rx),
+        });
+        log::info!("recording video to {}", path.display());
+        Ok(Self {
+            path,
+            frames: 0,
+            last_tick: None,
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Capture the framebuffer if the game has ticked since the last capture.
+    /// If several ticks passed, the frame is repeated so that timing is preserved.
+    pub fn capture(&mut self, ctx: &context::Context, fb: &framebuffer::Framebuffer, tick: Tick) {
+        let count = match self.last_tick {
+            None => 1,
+            Some(t) if tick > t => tick - t,
+            _ => return,
+        };
+        self.last_tick = Some(tick);
+        self.frames += count;
+        if let Some(tx) = &self.tx {
+            // the writer only hangs up if it failed, which finish() will report
+            let _ = tx.send((Image::from_framebuffer(ctx, fb), count));
+        }
+    }
+
+    /// Stop recording, waiting for the writer thread to flush all frames
+    pub fn finish(mut self) -> utils::Erm<()> {
+        self.tx = None;
+        let res = match self.thread.take().map(|t| t.join()) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => utils::erm_msg("video writer thread panicked"),
+            None => Ok(()),
+        };
+        log::info!("finished recording {} frames to {}", self.frames, self.path.display());
+        res
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_y4m(path: &std::path::Path, rx: std::sync::mpsc::Receiver<(Image, u64)>, width: u32, height: u32, fps: u32) -> utils::Erm<()> {
+    use std::io::Write;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED", width, height, fps)?;
+    let n = (width * height) as usize;
+    let mut planes = vec![0u8; n * 3];
+    for (img, count) in rx {
+        if img.width != width || img.height != height {
+            return utils::erm_msg("video frame size changed during recording");
+        }
+        // BT.601, limited range
+        for (i, px) in img.pixels.chunks_exact(4).enumerate() {
+            let (r, g, b) = (px[0] as f32, px[1] as f32, px[2] as f32);
+            planes[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+            planes[n + i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+            planes[2 * n + i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+        }
+        for _ in 0..count {
+            out.write_all(b"FRAME\n")?;
+            out.write_all(&planes)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_png_sequence(dir: &std::path::Path, rx: std::sync::mpsc::Receiver<(Image, u64)>) -> utils::Erm<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut idx = 0;
+    for (img, count) in rx {
+        let png = img.to_png()?;
+        for _ in 0..count {
+            std::fs::write(dir.join(format!("{:06}.png", idx)), &png)?;
+            idx += 1;
+        }
+    }
+    Ok(())
+}
@@ -30,6 +30,7 @@ pub struct Context {
     pub gl: glow::Context,
     pub emptyvao: glow::VertexArray,
     pub start_instant: std::time::Instant,
+    pub mouse_locked: std::cell::Cell<bool>,
 }
 
 
@@ -70,6 +71,7 @@ impl Context {
             gl,
             emptyvao,
             start_instant: std::time::Instant::now(),
+            mouse_locked: std::cell::Cell::new(false),
         };
         ret.init();
         ret
@@ -275,21 +277,53 @@ impl Context {
         }
     }
 
+    /// The browser hides the cursor while the pointer is locked, and may release the lock at any time (e.g. on Esc)
     #[cfg(target_arch = "wasm32")]
     pub fn lock_mouse(&self) {
         let _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
-        self.window.set_cursor_visible(false);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn unlock_mouse(&self) {
+        let _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::None);
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     pub fn lock_mouse(&self) {
         unsafe {
-            glfw::ffi::glfwSetInputMode(
-                self.window.borrow_mut().window_ptr(),
-                glfw::ffi::CURSOR,
-                glfw::ffi::CURSOR_DISABLED,
-            );
+            let ptr = self.window.borrow_mut().window_ptr();
+            glfw::ffi::glfwSetInputMode(ptr, glfw::ffi::CURSOR, glfw::ffi::CURSOR_DISABLED);
+            if glfw::ffi::glfwRawMouseMotionSupported() == glfw::ffi::TRUE {
+                glfw::ffi::glfwSetInputMode(ptr, glfw::ffi::RAW_MOUSE_MOTION, glfw::ffi::TRUE);
+            }
         }
+        self.mouse_locked.set(true);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn unlock_mouse(&self) {
+        unsafe {
+            let ptr = self.window.borrow_mut().window_ptr();
+            glfw::ffi::glfwSetInputMode(ptr, glfw::ffi::RAW_MOUSE_MOTION, glfw::ffi::FALSE);
+            glfw::ffi::glfwSetInputMode(ptr, glfw::ffi::CURSOR, glfw::ffi::CURSOR_NORMAL);
+        }
+        self.mouse_locked.set(false);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_mouse_locked(&self) -> bool { self.mouse_locked.get() }
+
+    /// Asks the document, since the lock can be released without going through unlock_mouse
+    #[cfg(target_arch = "wasm32")]
+    pub fn is_mouse_locked(&self) -> bool {
+        web_sys::window()
+            .and_then(|w| w.document())
+            .and_then(|d| d.pointer_lock_element())
+            .is_some()
+    }
+
+    pub fn toggle_mouse_lock(&self) {
+        if self.is_mouse_locked() { self.unlock_mouse() } else { self.lock_mouse() }
     }
 
     pub fn check_error(&self) {
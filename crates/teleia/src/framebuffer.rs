@@ -112,11 +112,13 @@ impl Framebuffer {
     }
 
     pub fn blit(&self, ctx: &context::Context, dest: &Self, pos: &glam::Vec2, scale: &glam::Vec2) {
+        // the screen is drawn within its viewport rather than from the corner
+        let src = if self.fbo.is_none() { self.offsets } else { glam::Vec2::ZERO };
         unsafe {
             ctx.gl.bind_framebuffer(glow::READ_FRAMEBUFFER, self.fbo);
             ctx.gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, dest.fbo);
             ctx.gl.blit_framebuffer(
-                0, 0, self.dims.x as _, self.dims.y as _,
+                src.x as _, src.y as _, (src.x + self.dims.x) as _, (src.y + self.dims.y) as _,
                 pos.x as _, pos.y as _, (pos.x + scale.x) as _, (pos.y + scale.y) as _,
                 glow::COLOR_BUFFER_BIT, glow::NEAREST
             );
@@ -146,6 +148,7 @@ impl Framebuffer {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn get_pixels_raw(&self, ctx: &context::Context, buf: &mut [u8]) {
         unsafe {
             ctx.gl.bind_texture(glow::TEXTURE_2D, self.tex);
@@ -155,4 +158,17 @@ impl Framebuffer {
             );
         }
     }
+
+    /// WebGL can't read textures back directly, so this reads the framebuffer's color attachment instead
+    #[cfg(target_arch = "wasm32")]
+    pub fn get_pixels_raw(&self, ctx: &context::Context, buf: &mut [u8]) {
+        unsafe {
+            ctx.gl.bind_framebuffer(glow::READ_FRAMEBUFFER, self.fbo);
+            ctx.gl.read_pixels(
+                0, 0, self.dims.x as _, self.dims.y as _,
+                glow::RGBA, glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(buf),
+            );
+        }
+    }
 }
@@ -0,0 +1,187 @@
+use enum_map::{Enum, EnumMap};
+use serde::{Serialize, Deserialize};
+use strum::EnumIter;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsCast;
+
+use crate::{context, state};
+
+pub const DEFAULT_DEADZONE: f32 = 0.2;
+/// How far a stick must be pushed (after the deadzone) to count as a direction press
+pub const DIGITAL_THRESHOLD: f32 = 0.5;
+
+/// Buttons of a gamepad with an Xbox-style layout
+#[derive(Debug, Enum, EnumIter, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Button {
+    A, B, X, Y,
+    LeftBumper, RightBumper,
+    Back, Start, Guide,
+    LeftThumb, RightThumb,
+    DPadUp, DPadRight, DPadDown, DPadLeft,
+}
+
+#[derive(Debug, Enum, EnumIter, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Axis {
+    LeftX, LeftY,
+    RightX, RightY,
+    LeftTrigger, RightTrigger,
+}
+
+fn apply_deadzone(v: f32, deadzone: f32) -> f32 {
+    if v.abs() <= deadzone { 0.0 } else {
+        v.signum() * (v.abs() - deadzone) / (1.0 - deadzone)
+    }
+}
+
+pub struct Gamepad {
+    pub connected: bool,
+    pub deadzone: f32,
+    pub buttons: EnumMap<Button, bool>,
+    /// Raw axis values as reported by the backend (sticks in [-1, 1], positive Y is down)
+    pub axes: EnumMap<Axis, f32>,
+    /// Virtual keys held by the left stick as of the last poll
+    pub keys: EnumMap<state::Key, bool>,
+    /// Whether we've already warned about a connected gamepad without the standard mapping
+    #[cfg(target_arch = "wasm32")]
+    warned_nonstandard: bool,
+}
+impl Default for Gamepad {
+    fn default() -> Self {
+        Self {
+            connected: false,
+            deadzone: DEFAULT_DEADZONE,
+            buttons: EnumMap::default(),
+            axes: EnumMap::default(),
+            keys: EnumMap::default(),
+            #[cfg(target_arch = "wasm32")]
+            warned_nonstandard: false,
+        }
+    }
+}
+impl Gamepad {
+    pub fn new() -> Self { Self::default() }
+
+    /// Axis value with the deadzone removed and the remaining range rescaled to [-1, 1]
+    pub fn axis(&self, a: Axis) -> f32 {
+        apply_deadzone(self.axes[a], self.deadzone)
+    }
+
+    fn stick(&self, x: Axis, y: Axis) -> glam::Vec2 {
+        let raw = glam::Vec2::new(self.axes[x], self.axes[y]);
+        let len = raw.length();
+        if len <= self.deadzone { glam::Vec2::ZERO } else {
+            raw / len * apply_deadzone(len.min(1.0), self.deadzone)
+        }
+    }
+    /// Left stick with a radial deadzone applied
+    pub fn left_stick(&self) -> glam::Vec2 { self.stick(Axis::LeftX, Axis::LeftY) }
+    /// Right stick with a radial deadzone applied
+    pub fn right_stick(&self) -> glam::Vec2 { self.stick(Axis::RightX, Axis::RightY) }
+
+    /// Which directional virtual keys the left stick is currently pushing
+    pub fn stick_keys(&self) -> EnumMap<state::Key, bool> {
+        let mut ret: EnumMap<state::Key, bool> = EnumMap::default();
+        let stick = self.left_stick();
+        if stick.x <= -DIGITAL_THRESHOLD { ret[state::Key::Left] = true; }
+        if stick.x >= DIGITAL_THRESHOLD { ret[state::Key::Right] = true; }
+        if stick.y <= -DIGITAL_THRESHOLD { ret[state::Key::Up] = true; }
+        if stick.y >= DIGITAL_THRESHOLD { ret[state::Key::Down] = true; }
+        ret
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.buttons = EnumMap::default();
+        self.axes = EnumMap::default();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn poll(&mut self, ctx: &context::Context) {
+        let glfw = ctx.glfw.borrow_mut();
+        let state = [
+            glfw::JoystickId::Joystick1, glfw::JoystickId::Joystick2,
+            glfw::JoystickId::Joystick3, glfw::JoystickId::Joystick4,
+        ].into_iter().find_map(|id| glfw.get_joystick(id).get_gamepad_state());
+        let Some(state) = state else { self.disconnect(); return };
+        self.connected = true;
+        let pressed = |b| state.get_button_state(b) == glfw::Action::Press;
+        self.buttons = enum_map::enum_map! {
+            Button::A => pressed(glfw::GamepadButton::ButtonA),
+            Button::B => pressed(glfw::GamepadButton::ButtonB),
+            Button::X => pressed(glfw::GamepadButton::ButtonX),
+            Button::Y => pressed(glfw::GamepadButton::ButtonY),
+            Button::LeftBumper => pressed(glfw::GamepadButton::ButtonLeftBumper),
+            Button::RightBumper => pressed(glfw::GamepadButton::ButtonRightBumper),
+            Button::Back => pressed(glfw::GamepadButton::ButtonBack),
+            Button::Start => pressed(glfw::GamepadButton::ButtonStart),
+            Button::Guide => pressed(glfw::GamepadButton::ButtonGuide),
+            Button::LeftThumb => pressed(glfw::GamepadButton::ButtonLeftThumb),
+            Button::RightThumb => pressed(glfw::GamepadButton::ButtonRightThumb),
+            Button::DPadUp => pressed(glfw::GamepadButton::ButtonDpadUp),
+            Button::DPadRight => pressed(glfw::GamepadButton::ButtonDpadRight),
+            Button::DPadDown => pressed(glfw::GamepadButton::ButtonDpadDown),
+            Button::DPadLeft => pressed(glfw::GamepadButton::ButtonDpadLeft),
+        };
+        self.axes = enum_map::enum_map! {
+            Axis::LeftX => state.get_axis(glfw::GamepadAxis::AxisLeftX),
+            Axis::LeftY => state.get_axis(glfw::GamepadAxis::AxisLeftY),
+            Axis::RightX => state.get_axis(glfw::GamepadAxis::AxisRightX),
+            Axis::RightY => state.get_axis(glfw::GamepadAxis::AxisRightY),
+            // GLFW reports triggers in [-1, 1], the browser in [0, 1]
+            Axis::LeftTrigger => (state.get_axis(glfw::GamepadAxis::AxisLeftTrigger) + 1.0) / 2.0,
+            Axis::RightTrigger => (state.get_axis(glfw::GamepadAxis::AxisRightTrigger) + 1.0) / 2.0,
+        };
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn poll(&mut self, _ctx: &context::Context) {
+        let pads: Vec<web_sys::Gamepad> = web_sys::window()
+            .and_then(|w| w.navigator().get_gamepads().ok())
+            .map(|pads| pads.iter().filter_map(|p| {
+                p.dyn_into::<web_sys::Gamepad>().ok().filter(|g| g.connected())
+            }).collect())
+            .unwrap_or_default();
+        // the button and axis indices below only mean anything for the standard mapping
+        let pad = pads.iter().find(|g| g.mapping() == web_sys::GamepadMappingType::Standard);
+        if pad.is_none() && let Some(g) = pads.first() && !self.warned_nonstandard {
+            log::warn!("ignoring gamepad without the standard mapping: {}", g.id());
+            self.warned_nonstandard = true;
+        }
+        let Some(pad) = pad else { self.disconnect(); return };
+        self.connected = true;
+        let buttons: Vec<web_sys::GamepadButton> = pad.buttons().iter()
+            .filter_map(|b| b.dyn_into::<web_sys::GamepadButton>().ok())
+            .collect();
+        let pressed = |i: usize| buttons.get(i).is_some_and(|b| b.pressed());
+        let value = |i: usize| buttons.get(i).map(|b| b.value() as f32).unwrap_or(0.0);
+        let axes: Vec<f32> = pad.axes().iter().map(|a| a.as_f64().unwrap_or(0.0) as f32).collect();
+        let axis = |i: usize| axes.get(i).copied().unwrap_or(0.0);
+        // indices are from the "standard" gamepad mapping
+        self.buttons = enum_map::enum_map! {
+            Button::A => pressed(0),
+            Button::B => pressed(1),
+            Button::X => pressed(2),
+            Button::Y => pressed(3),
+            Button::LeftBumper => pressed(4),
+            Button::RightBumper => pressed(5),
+            Button::Back => pressed(8),
+            Button::Start => pressed(9),
+            Button::LeftThumb => pressed(10),
+            Button::RightThumb => pressed(11),
+            Button::DPadUp => pressed(12),
+            Button::DPadDown => pressed(13),
+            Button::DPadLeft => pressed(14),
+            Button::DPadRight => pressed(15),
+            Button::Guide => pressed(16),
+        };
+        self.axes = enum_map::enum_map! {
+            Axis::LeftX => axis(0),
+            Axis::LeftY => axis(1),
+            Axis::RightX => axis(2),
+            Axis::RightY => axis(3),
+            Axis::LeftTrigger => value(6),
+            Axis::RightTrigger => value(7),
+        };
+    }
+}
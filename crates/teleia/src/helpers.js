@@ -19,3 +19,15 @@ export function js_bundt_api_server() {
 export function js_bundt_secure_api_server() {
     return globalThis.secureApiServer || "http://localhost:8000/api";
 }
+
+export function js_download_bytes(name, mime, bytes) {
+    const blob = new Blob([bytes], { type: mime });
+    const url = URL.createObjectURL(blob);
+    const a = document.createElement("a");
+    a.href = url;
+    a.download = name;
+    document.body.appendChild(a);
+    a.click();
+    a.remove();
+    setTimeout(() => URL.revokeObjectURL(url), 1000);
+}
@@ -1 +1,4 @@
+pub mod collision;
+pub mod ldtk;
+pub mod pathfinding;
 pub mod tiled;
@@ -0,0 +1,257 @@
+// Axis-aligned collision for tile maps.
+// Everything here is in tile units with y pointing down, the same space LevelRenderer draws in.
+
+const EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub min: glam::Vec2,
+    pub max: glam::Vec2,
+}
+impl Rect {
+    pub fn new(pos: glam::Vec2, dims: glam::Vec2) -> Self {
+        Self { min: pos, max: pos + dims }
+    }
+    pub fn dims(&self) -> glam::Vec2 { self.max - self.min }
+    pub fn center(&self) -> glam::Vec2 { (self.min + self.max) / 2.0 }
+    pub fn translate(&self, d: glam::Vec2) -> Self {
+        Self { min: self.min + d, max: self.max + d }
+    }
+    /// True if the interiors intersect (touching edges don't count)
+    pub fn overlaps(&self, o: &Self) -> bool {
+        self.min.x < o.max.x && o.min.x < self.max.x
+            && self.min.y < o.max.y && o.min.y < self.max.y
+    }
+}
+
+/// Which sides of a moving box ran into something
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Blocked {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    /// For platformers, this means the box is standing on something
+    pub down: bool,
+}
+impl Blocked {
+    pub fn any(&self) -> bool { self.left || self.right || self.up || self.down }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sweep {
+    /// Where the box ended up
+    pub rect: Rect,
+    /// How far it actually moved
+    pub delta: glam::Vec2,
+    pub blocked: Blocked,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CollisionMap {
+    /// Tile position of the first entry in solid. Infinite maps can extend into negative coordinates.
+    pub origin: glam::IVec2,
+    pub width: i32,
+    pub height: i32,
+    solid: Vec<bool>,
+    /// Solid tiles merged into as few rectangles as possible, followed by partial-tile shapes
+    pub rects: Vec<Rect>,
+}
+impl CollisionMap {
+    /// solid is row-major with width * height entries.
+    /// shapes are extra rectangles for tiles that are only partly solid.
+    pub fn new(width: i32, height: i32, solid: Vec<bool>, shapes: Vec<Rect>) -> Self {
+        Self::with_origin(glam::IVec2::ZERO, width, height, solid, shapes)
+    }
+
+    /// Like new, but solid starts at the given tile rather than (0, 0)
+    pub fn with_origin(origin: glam::IVec2, width: i32, height: i32, solid: Vec<bool>, shapes: Vec<Rect>) -> Self {
+        let mut ret = Self { origin, width, height, solid, rects: Vec::new() };
+        ret.merge();
+        ret.rects.extend(shapes);
+        ret
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        let (x, y) = (x - self.origin.x, y - self.origin.y);
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    /// Whether the whole tile is solid. Tiles outside the map are not.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        self.in_bounds(x, y) && self.solid_local(x - self.origin.x, y - self.origin.y)
+    }
+
+    /// Like is_solid, relative to the origin and without the bounds check
+    fn solid_local(&self, x: i32, y: i32) -> bool {
+        self.solid.get((x + y * self.width) as usize).copied().unwrap_or(false)
+    }
+
+    /// Greedily merge runs of solid tiles into rectangles: extend right, then down
+    fn merge(&mut self) {
+        let (w, h) = (self.width, self.height);
+        let mut used = vec![false; self.solid.len()];
+        let free = |used: &[bool], x: i32, y: i32| self.solid_local(x, y) && !used[(x + y * w) as usize];
+        let origin = self.origin.as_vec2();
+        let mut rects = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                if !free(&used, x, y) { continue; }
+                let mut x1 = x;
+                while x1 + 1 < w && free(&used, x1 + 1, y) { x1 += 1; }
+                let mut y1 = y;
+                while y1 + 1 < h && (x..=x1).all(|xx| free(&used, xx, y1 + 1)) { y1 += 1; }
+                for yy in y..=y1 {
+                    for xx in x..=x1 {
+                        used[(xx + yy * w) as usize] = true;
+                    }
+                }
+                rects.push(Rect {
+                    min: origin + glam::Vec2::new(x as f32, y as f32),
+                    max: origin + glam::Vec2::new((x1 + 1) as f32, (y1 + 1) as f32),
+                });
+            }
+        }
+        self.rects = rects;
+    }
+
+    pub fn overlapping<'a>(&'a self, r: &'a Rect) -> impl Iterator<Item=&'a Rect> + 'a {
+        self.rects.iter().filter(move |s| s.overlaps(r))
+    }
+
+    /// How far the box can move along one axis before touching a solid
+    fn sweep_axis(&self, r: &Rect, d: f32, axis: usize) -> f32 {
+        if d == 0.0 { return 0.0; }
+        let other = 1 - axis;
+        let mut d = d;
+        for s in self.rects.iter() {
+            if s.min[other] >= r.max[other] || s.max[other] <= r.min[other] { continue; }
+            if d > 0.0 && s.min[axis] >= r.max[axis] - EPSILON {
+                d = d.min((s.min[axis] - r.max[axis]).max(0.0));
+            } else if d < 0.0 && s.max[axis] <= r.min[axis] + EPSILON {
+                d = d.max((s.max[axis] - r.min[axis]).min(0.0));
+            }
+        }
+        d
+    }
+
+    /// Move a box by delta, first horizontally and then vertically, stopping at solids.
+    /// Works for any speed since the whole path is checked, not just the destination.
+    pub fn sweep(&self, r: Rect, delta: glam::Vec2) -> Sweep {
+        let mut blocked = Blocked::default();
+        let dx = self.sweep_axis(&r, delta.x, 0);
+        if dx != delta.x {
+            if delta.x > 0.0 { blocked.right = true } else { blocked.left = true }
+        }
+        let r = r.translate(glam::Vec2::new(dx, 0.0));
+        let dy = self.sweep_axis(&r, delta.y, 1);
+        if dy != delta.y {
+            if delta.y > 0.0 { blocked.down = true } else { blocked.up = true }
+        }
+        Sweep {
+            rect: r.translate(glam::Vec2::new(0.0, dy)),
+            delta: glam::Vec2::new(dx, dy),
+            blocked,
+        }
+    }
+
+    /// Push a box that is already inside solids (e.g. after teleporting) out along the shallowest axis
+    pub fn resolve(&self, r: Rect) -> Rect {
+        let mut r = r;
+        for s in self.rects.iter() {
+            if !s.overlaps(&r) { continue; }
+            let push_left = s.min.x - r.max.x;
+            let push_right = s.max.x - r.min.x;
+            let push_up = s.min.y - r.max.y;
+            let push_down = s.max.y - r.min.y;
+            let px = if -push_left < push_right { push_left } else { push_right };
+            let py = if -push_up < push_down { push_up } else { push_down };
+            r = if px.abs() < py.abs() {
+                r.translate(glam::Vec2::new(px, 0.0))
+            } else {
+                r.translate(glam::Vec2::new(0.0, py))
+            };
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(rows: &[&str]) -> CollisionMap {
+        let solid = rows.iter().flat_map(|r| r.chars().map(|c| c == '#')).collect();
+        CollisionMap::new(rows[0].len() as i32, rows.len() as i32, solid, Vec::new())
+    }
+
+    fn rect(x0: f32, y0: f32, x1: f32, y1: f32) -> Rect {
+        Rect { min: glam::Vec2::new(x0, y0), max: glam::Vec2::new(x1, y1) }
+    }
+
+    #[test]
+    fn merge_extends_right_then_down() {
+        let m = map(&[
+            "##.",
+            "##.",
+            "..#",
+        ]);
+        assert_eq!(m.rects, vec![rect(0.0, 0.0, 2.0, 2.0), rect(2.0, 2.0, 3.0, 3.0)]);
+    }
+
+    #[test]
+    fn merge_covers_every_solid_tile_once() {
+        let m = map(&[
+            "###.",
+            "#.##",
+            "####",
+        ]);
+        let area: f32 = m.rects.iter().map(|r| r.dims().x * r.dims().y).sum();
+        assert_eq!(area, 10.0);
+        for (i, a) in m.rects.iter().enumerate() {
+            assert!(m.rects[i + 1..].iter().all(|b| !a.overlaps(b)));
+        }
+    }
+
+    #[test]
+    fn origin_offsets_tiles_and_rects() {
+        let m = CollisionMap::with_origin(glam::IVec2::new(-2, -1), 2, 1, vec![false, true], Vec::new());
+        assert!(m.is_solid(-1, -1));
+        assert!(!m.is_solid(1, 0));
+        assert!(!m.in_bounds(0, -1));
+        assert_eq!(m.rects, vec![rect(-1.0, -1.0, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn sweep_stops_at_walls() {
+        let m = map(&[
+            "....#",
+            ".....",
+            "#####",
+        ]);
+        let r = Rect::new(glam::Vec2::new(0.5, 0.5), glam::Vec2::splat(1.0));
+        let s = m.sweep(r, glam::Vec2::new(10.0, 10.0));
+        assert_eq!(s.delta, glam::Vec2::new(2.5, 0.5));
+        assert!(s.blocked.right && s.blocked.down);
+        assert!(!s.blocked.left && !s.blocked.up);
+        let free = m.sweep(r, glam::Vec2::new(0.25, 0.0));
+        assert_eq!(free.delta, glam::Vec2::new(0.25, 0.0));
+        assert!(!free.blocked.any());
+    }
+
+    #[test]
+    fn sweep_does_not_tunnel() {
+        let m = map(&["..#.........."]);
+        let r = Rect::new(glam::Vec2::ZERO, glam::Vec2::new(1.0, 1.0));
+        assert_eq!(m.sweep(r, glam::Vec2::new(100.0, 0.0)).rect.min.x, 1.0);
+    }
+
+    #[test]
+    fn resolve_pushes_out_along_the_shallowest_axis() {
+        let m = map(&[
+            "...",
+            "###",
+        ]);
+        let r = Rect::new(glam::Vec2::new(1.0, 0.75), glam::Vec2::splat(0.5));
+        assert_eq!(m.resolve(r), Rect::new(glam::Vec2::new(1.0, 0.5), glam::Vec2::splat(0.5)));
+    }
+}
@@ -0,0 +1,522 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use serde::Deserialize;
+
+use crate::{context, shader, texture, Erm};
+use super::{collision, tiled};
+
+#[derive(Debug)]
+pub enum Err {
+    LevelNotFound(String),
+    LayerNotFound(String),
+    TilesetNotFound(i32),
+    AssetNotFound(String),
+}
+impl std::error::Error for Err {}
+impl std::fmt::Display for Err {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LevelNotFound(l) => write!(f, "level not found: {}", l),
+            Self::LayerNotFound(l) => write!(f, "layer not found: {}", l),
+            Self::TilesetNotFound(uid) => write!(f, "tileset not found: {}", uid),
+            Self::AssetNotFound(ass) => write!(f, "asset not found: {}", ass),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum WorldLayout {
+    Free,
+    GridVania,
+    LinearHorizontal,
+    LinearVertical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum LayerType {
+    IntGrid,
+    Entities,
+    Tiles,
+    AutoLayer,
+}
+
+/// A custom field on a level or entity
+#[derive(Debug, Clone, Deserialize)]
+pub struct Field {
+    #[serde(rename = "__identifier")] pub identifier: String,
+    #[serde(rename = "__type")] pub ty: String,
+    #[serde(rename = "__value")] pub value: serde_json::Value,
+}
+pub fn field<'a>(fields: &'a [Field], name: &str) -> Option<&'a serde_json::Value> {
+    fields.iter().find(|f| f.identifier == name).map(|f| &f.value)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntGridValue {
+    pub value: i32,
+    pub identifier: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerDef {
+    pub identifier: String,
+    pub uid: i32,
+    #[serde(rename = "type")] pub ty: LayerType,
+    pub grid_size: i32,
+    #[serde(default)] pub int_grid_values: Vec<IntGridValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TilesetDef {
+    pub uid: i32,
+    pub identifier: String,
+    /// Path of the image relative to the project, used as the key in Assets
+    pub rel_path: Option<String>,
+    pub px_wid: i32, pub px_hei: i32,
+    pub tile_grid_size: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Defs {
+    pub layers: Vec<LayerDef>,
+    pub tilesets: Vec<TilesetDef>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct TileInstance {
+    /// Position in the layer, in pixels
+    pub px: [i32; 2],
+    /// Position in the tileset image, in pixels
+    pub src: [i32; 2],
+    /// Bit 0 is a horizontal flip, bit 1 is vertical
+    pub f: u8,
+    /// Tile ID in the tileset
+    pub t: i32,
+}
+impl TileInstance {
+    pub fn flip(&self) -> tiled::Flip {
+        let mut ret = tiled::Flip::empty();
+        if self.f & 1 != 0 { ret |= tiled::Flip::HORIZONTAL; }
+        if self.f & 2 != 0 { ret |= tiled::Flip::VERTICAL; }
+        ret
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entity {
+    #[serde(rename = "__identifier")] pub identifier: String,
+    /// Cell the entity's pivot is in
+    #[serde(rename = "__grid")] pub grid: [i32; 2],
+    #[serde(rename = "__pivot")] pub pivot: [f32; 2],
+    #[serde(rename = "__tags", default)] pub tags: Vec<String>,
+    pub iid: String,
+    pub width: i32, pub height: i32,
+    /// Position of the pivot in the level, in pixels
+    pub px: [i32; 2],
+    #[serde(rename = "fieldInstances", default)] pub fields: Vec<Field>,
+}
+impl Entity {
+    pub fn field(&self, name: &str) -> Option<&serde_json::Value> {
+        field(&self.fields, name)
+    }
+    pub fn position(&self) -> glam::Vec2 {
+        glam::Vec2::new(self.px[0] as f32, self.px[1] as f32)
+    }
+    /// Bounding box in level pixels, accounting for the pivot
+    pub fn rect(&self) -> collision::Rect {
+        let dims = glam::Vec2::new(self.width as f32, self.height as f32);
+        collision::Rect::new(self.position() - dims * glam::Vec2::from(self.pivot), dims)
+    }
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LayerInstance {
+    #[serde(rename = "__identifier")] pub identifier: String,
+    #[serde(rename = "__type")] pub ty: LayerType,
+    /// Dimensions in cells
+    #[serde(rename = "__cWid")] pub c_wid: i32,
+    #[serde(rename = "__cHei")] pub c_hei: i32,
+    #[serde(rename = "__gridSize")] pub grid_size: i32,
+    #[serde(rename = "__pxTotalOffsetX")] pub px_offset_x: i32,
+    #[serde(rename = "__pxTotalOffsetY")] pub px_offset_y: i32,
+    #[serde(rename = "__tilesetDefUid")] pub tileset_uid: Option<i32>,
+    #[serde(rename = "layerDefUid")] pub def_uid: i32,
+    pub visible: bool,
+    /// Row-major IntGrid values, 0 for empty cells
+    #[serde(rename = "intGridCsv", default)] pub int_grid: Vec<i32>,
+    #[serde(rename = "autoLayerTiles", default)] pub auto_tiles: Vec<TileInstance>,
+    #[serde(rename = "gridTiles", default)] pub grid_tiles: Vec<TileInstance>,
+    #[serde(rename = "entityInstances", default)] pub entities: Vec<Entity>,
+}
+impl LayerInstance {
+    /// IntGrid value of a cell, 0 if empty or out of bounds
+    pub fn int_value(&self, x: i32, y: i32) -> i32 {
+        if x < 0 || y < 0 || x >= self.c_wid || y >= self.c_hei { return 0; }
+        self.int_grid.get((x + y * self.c_wid) as usize).copied().unwrap_or(0)
+    }
+    /// Tiles to draw: hand-placed tiles for Tiles layers, generated ones for auto-layers and IntGrid layers
+    pub fn tiles(&self) -> impl Iterator<Item=&TileInstance> {
+        self.grid_tiles.iter().chain(self.auto_tiles.iter())
+    }
+    /// Collision in cell units from an IntGrid layer, with cells solid where the predicate holds.
+    /// Use to_cells and rect_to_cells to test things positioned in pixels against it.
+    pub fn collision<F>(&self, solid: F) -> collision::CollisionMap where F: Fn(i32) -> bool {
+        let cells = (0..self.c_hei)
+            .flat_map(|y| (0..self.c_wid).map(move |x| (x, y)))
+            .map(|(x, y)| solid(self.int_value(x, y)))
+            .collect();
+        collision::CollisionMap::new(self.c_wid, self.c_hei, cells, Vec::new())
+    }
+    /// In pixels
+    pub fn offset(&self) -> glam::Vec2 {
+        glam::Vec2::new(self.px_offset_x as f32, self.px_offset_y as f32)
+    }
+    /// Convert a position in level pixels (as entities use) to this layer's cells
+    pub fn to_cells(&self, v: glam::Vec2) -> glam::Vec2 {
+        (v - self.offset()) / self.grid_size as f32
+    }
+    /// Convert a position in this layer's cells to level pixels
+    pub fn to_pixels(&self, v: glam::Vec2) -> glam::Vec2 {
+        v * self.grid_size as f32 + self.offset()
+    }
+    /// Convert a box in level pixels (e.g. Entity::rect) to this layer's cells
+    pub fn rect_to_cells(&self, r: &collision::Rect) -> collision::Rect {
+        collision::Rect { min: self.to_cells(r.min), max: self.to_cells(r.max) }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Level {
+    pub identifier: String,
+    pub iid: String,
+    pub uid: i32,
+    /// -1 in linear world layouts, see Project::level_positions
+    pub world_x: i32, pub world_y: i32,
+    #[serde(default)] pub world_depth: i32,
+    pub px_wid: i32, pub px_hei: i32,
+    #[serde(rename = "fieldInstances", default)] pub fields: Vec<Field>,
+    /// Listed topmost first. None if the project saves levels in separate files and this one isn't loaded yet.
+    pub layer_instances: Option<Vec<LayerInstance>>,
+    pub external_rel_path: Option<String>,
+}
+impl Level {
+    /// Fill in the layers of a level saved in a separate .ldtkl file
+    pub fn load_external(&mut self, bytes: &str) -> Erm<()> {
+        let ext: Level = serde_json::from_str(bytes)?;
+        self.layer_instances = ext.layer_instances;
+        Ok(())
+    }
+    pub fn field(&self, name: &str) -> Option<&serde_json::Value> {
+        field(&self.fields, name)
+    }
+    pub fn layers(&self) -> &[LayerInstance] {
+        self.layer_instances.as_deref().unwrap_or_default()
+    }
+    pub fn layer(&self, identifier: &str) -> Option<&LayerInstance> {
+        self.layers().iter().find(|l| l.identifier == identifier)
+    }
+    /// Every entity in every entity layer
+    pub fn entities(&self) -> impl Iterator<Item=&Entity> {
+        self.layers().iter().flat_map(|l| l.entities.iter())
+    }
+    pub fn entities_of<'a>(&'a self, identifier: &'a str) -> impl Iterator<Item=&'a Entity> + 'a {
+        self.entities().filter(move |e| e.identifier == identifier)
+    }
+    pub fn find_entity(&self, identifier: &str) -> Option<&Entity> {
+        self.entities().find(|e| e.identifier == identifier)
+    }
+    /// IntGrid value of a cell in the named layer
+    pub fn int_value(&self, layer: &str, x: i32, y: i32) -> Erm<i32> {
+        Ok(self.layer(layer).ok_or(Err::LayerNotFound(layer.to_string()))?.int_value(x, y))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct World {
+    pub identifier: String,
+    pub world_layout: Option<WorldLayout>,
+    pub levels: Vec<Level>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub json_version: String,
+    pub world_layout: Option<WorldLayout>,
+    pub default_grid_size: i32,
+    pub defs: Defs,
+    pub levels: Vec<Level>,
+    /// Only used by projects with multiple worlds, in which case levels is empty
+    #[serde(default)] pub worlds: Vec<World>,
+}
+impl Project {
+    pub fn new(bytes: &str) -> Erm<Self> {
+        Ok(serde_json::from_str(bytes)?)
+    }
+    /// Levels of the first world (or the only one)
+    pub fn world_levels(&self) -> &[Level] {
+        match self.worlds.first() {
+            Some(w) if self.levels.is_empty() => &w.levels,
+            _ => &self.levels,
+        }
+    }
+    pub fn layout(&self) -> Option<WorldLayout> {
+        match self.worlds.first() {
+            Some(w) if self.levels.is_empty() => w.world_layout,
+            _ => self.world_layout,
+        }
+    }
+    pub fn level(&self, identifier: &str) -> Erm<&Level> {
+        Ok(self.world_levels().iter().find(|l| l.identifier == identifier)
+            .ok_or(Err::LevelNotFound(identifier.to_string()))?)
+    }
+    /// For filling in external levels, see Level::load_external
+    pub fn level_mut(&mut self, identifier: &str) -> Erm<&mut Level> {
+        let levels = if self.levels.is_empty() && let Some(w) = self.worlds.first_mut() {
+            &mut w.levels
+        } else {
+            &mut self.levels
+        };
+        Ok(levels.iter_mut().find(|l| l.identifier == identifier)
+            .ok_or(Err::LevelNotFound(identifier.to_string()))?)
+    }
+    pub fn tileset(&self, uid: i32) -> Erm<&TilesetDef> {
+        Ok(self.defs.tilesets.iter().find(|t| t.uid == uid).ok_or(Err::TilesetNotFound(uid))?)
+    }
+    pub fn layer_def(&self, uid: i32) -> Option<&LayerDef> {
+        self.defs.layers.iter().find(|l| l.uid == uid)
+    }
+    /// Name given to an IntGrid value in the layer's definition
+    pub fn int_grid_identifier(&self, layer: &LayerInstance, value: i32) -> Option<&str> {
+        self.layer_def(layer.def_uid)?
+            .int_grid_values.iter().find(|v| v.value == value)?
+            .identifier.as_deref()
+    }
+    /// Position of each level in the world, in pixels.
+    /// Linear layouts don't store positions, so levels are laid out end to end in order.
+    pub fn level_positions(&self) -> Vec<glam::Vec2> {
+        let mut acc = 0;
+        self.world_levels().iter().map(|l| match self.layout() {
+            Some(WorldLayout::LinearHorizontal) => {
+                let ret = glam::Vec2::new(acc as f32, 0.0);
+                acc += l.px_wid;
+                ret
+            },
+            Some(WorldLayout::LinearVertical) => {
+                let ret = glam::Vec2::new(0.0, acc as f32);
+                acc += l.px_hei;
+                ret
+            },
+            _ => glam::Vec2::new(l.world_x as f32, l.world_y as f32),
+        }).collect()
+    }
+}
+
+/// Tileset images, keyed by their path relative to the project
+#[derive(Default)]
+pub struct Assets {
+    textures: HashMap<String, texture::Texture>,
+}
+impl Assets {
+    pub fn new() -> Self { Self::default() }
+    pub fn load(&mut self, ctx: &context::Context, rel_path: &str, img: &[u8]) {
+        if self.textures.insert(rel_path.to_string(), texture::Texture::new(ctx, img)).is_some() {
+            log::warn!("duplicate tileset image: {}", rel_path);
+        }
+    }
+    pub fn get(&self, rel_path: &str) -> Erm<&texture::Texture> {
+        Ok(self.textures.get(rel_path).ok_or(Err::AssetNotFound(rel_path.to_string()))?)
+    }
+}
+
+/// Draws the levels of a project at their world positions, in pixel units.
+/// Unlike tiled::LevelRenderer, which works in tiles, positions here are pixels because
+/// LDtk layers can have different grid sizes; LayerInstance::to_cells converts to a layer's cells.
+/// Levels are populated when they come on screen and freed once they are well off it.
+pub struct LevelRenderer {
+    pub shader: shader::Shader,
+    /// Geometry of each layer with tiles of each populated level (by uid), bottom layer first
+    pub levels: HashMap<i32, Vec<tiled::ChunkRenderer>>,
+    /// World position in pixels shown at the center of the screen
+    pub camera: glam::Vec2,
+}
+impl LevelRenderer {
+    pub fn new(ctx: &context::Context) -> Self {
+        Self {
+            shader: shader::Shader::new_nolib(
+                ctx,
+                include_str!("../assets/shaders/tiled/vert.glsl"),
+                include_str!("../assets/shaders/tiled/frag.glsl"),
+            ),
+            levels: HashMap::new(),
+            camera: glam::Vec2::ZERO,
+        }
+    }
+    pub fn populate_level(&mut self, ctx: &context::Context, project: &Project, level: &Level) -> Erm<()> {
+        let mut layers = Vec::new();
+        for layer in level.layers().iter().rev() {
+            let ts = match layer.tileset_uid {
+                Some(uid) if layer.visible && layer.tiles().next().is_some() => Some(project.tileset(uid)?),
+                _ => None,
+            };
+            if let Some(ts) = ts && let Some(source) = ts.rel_path.as_deref() {
+                let mut cr = tiled::ChunkRenderer::new(ctx)?;
+                let img = glam::Vec2::new(ts.px_wid as f32, ts.px_hei as f32);
+                let tdims = glam::Vec2::splat(ts.tile_grid_size as f32) / img;
+                let size = layer.grid_size as f32;
+                let mut vertices = Vec::new();
+                let mut texcoords = Vec::new();
+                let mut indices = Vec::new();
+                for t in layer.tiles() {
+                    let i = vertices.len() as u32;
+                    let pos = layer.offset() + glam::Vec2::new(t.px[0] as f32, t.px[1] as f32);
+                    let uvbase = glam::Vec2::new(t.src[0] as f32, t.src[1] as f32) / img;
+                    let flip = t.flip();
+                    for corner in [
+                        glam::Vec2::new(0.0, 0.0),
+                        glam::Vec2::new(1.0, 0.0),
+                        glam::Vec2::new(1.0, 1.0),
+                        glam::Vec2::new(0.0, 1.0),
+                    ] {
+                        vertices.push(pos + corner * size);
+                        // same texture orientation as the Tiled renderer
+                        let s = flip.sample(corner);
+                        texcoords.push(uvbase + glam::Vec2::new(s.x, 1.0 - s.y) * tdims);
+                    }
+                    indices.push(i); indices.push(i + 1); indices.push(i + 2);
+                    indices.push(i); indices.push(i + 3); indices.push(i + 2);
+                }
+                let animations = vec![0.0; vertices.len()];
+                cr.upload(ctx, &vertices, &texcoords, &animations, vec![(source, indices)]);
+                layers.push(cr);
+            }
+        }
+        if let Some(old) = self.levels.insert(level.uid, layers) {
+            for cr in old { cr.delete(ctx); }
+        }
+        Ok(())
+    }
+    pub fn render(
+        &mut self,
+        ctx: &context::Context,
+        assets: &Assets,
+        project: &Project,
+    ) -> Erm<()> {
+        self.shader.bind(ctx);
+        let half = glam::Vec2::new(ctx.render_width, ctx.render_height) / 2.0;
+        let view = collision::Rect { min: self.camera - half, max: self.camera + half };
+        // levels are kept until they're half a screen off it, so moving back and forth doesn't rebuild them
+        let margin = collision::Rect { min: view.min - half, max: view.max + half };
+        let positions = project.level_positions();
+        let mut visible = Vec::new();
+        let mut nearby = Vec::new();
+        for (level, pos) in project.world_levels().iter().zip(positions) {
+            let bounds = collision::Rect::new(pos, glam::Vec2::new(level.px_wid as f32, level.px_hei as f32));
+            if level.layer_instances.is_none() { continue; }
+            if bounds.overlaps(&margin) { nearby.push(level.uid); }
+            if bounds.overlaps(&view) { visible.push((level, pos)); }
+        }
+        self.levels.retain(|uid, layers| {
+            let keep = nearby.contains(uid);
+            if !keep { for cr in layers.iter() { cr.delete(ctx); } }
+            keep
+        });
+        for (level, pos) in visible {
+            if !self.levels.contains_key(&level.uid) {
+                self.populate_level(ctx, project, level)?;
+            }
+            self.shader.set_mat4(
+                ctx, "transform",
+                &glam::Mat4::from_scale(glam::Vec3::new(2.0 / ctx.render_width, -2.0 / ctx.render_height, 1.0))
+                    .mul_mat4(&glam::Mat4::from_translation((pos - self.camera).extend(0.0))),
+            );
+            if let Some(layers) = self.levels.get(&level.uid) {
+                for cr in layers.iter() {
+                    cr.draw(ctx, |src| assets.get(src))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(uid: i32, world: [i32; 2], dims: [i32; 2]) -> serde_json::Value {
+        serde_json::json!({
+            "identifier": format!("Level_{}", uid), "iid": format!("iid-{}", uid), "uid": uid,
+            "worldX": world[0], "worldY": world[1], "pxWid": dims[0], "pxHei": dims[1],
+            "layerInstances": [],
+        })
+    }
+
+    fn project(layout: &str, levels: Vec<serde_json::Value>) -> Project {
+        Project::new(&serde_json::json!({
+            "jsonVersion": "1.5.3", "worldLayout": layout, "defaultGridSize": 16,
+            "defs": { "layers": [], "tilesets": [] },
+            "levels": levels,
+        }).to_string()).unwrap()
+    }
+
+    fn positions(p: &Project) -> Vec<(f32, f32)> {
+        p.level_positions().iter().map(|v| (v.x, v.y)).collect()
+    }
+
+    #[test]
+    fn linear_layouts_place_levels_end_to_end() {
+        let levels = || vec![level(0, [-1, -1], [256, 128]), level(1, [-1, -1], [64, 320]), level(2, [-1, -1], [16, 16])];
+        let h = project("LinearHorizontal", levels());
+        assert_eq!(positions(&h), vec![(0.0, 0.0), (256.0, 0.0), (320.0, 0.0)]);
+        let v = project("LinearVertical", levels());
+        assert_eq!(positions(&v), vec![(0.0, 0.0), (0.0, 128.0), (0.0, 448.0)]);
+    }
+
+    #[test]
+    fn free_layouts_use_world_positions() {
+        let p = project("Free", vec![level(0, [0, 0], [256, 128]), level(1, [-300, 512], [64, 64])]);
+        assert_eq!(positions(&p), vec![(0.0, 0.0), (-300.0, 512.0)]);
+    }
+
+    #[test]
+    fn multiple_worlds_use_the_first() {
+        let p = Project::new(&serde_json::json!({
+            "jsonVersion": "1.5.3", "worldLayout": null, "defaultGridSize": 16,
+            "defs": { "layers": [], "tilesets": [] },
+            "levels": [],
+            "worlds": [{
+                "identifier": "World", "worldLayout": "LinearHorizontal",
+                "levels": [level(0, [-1, -1], [100, 10]), level(1, [-1, -1], [50, 10])],
+            }],
+        }).to_string()).unwrap();
+        assert_eq!(positions(&p), vec![(0.0, 0.0), (100.0, 0.0)]);
+        assert_eq!(p.level("Level_1").unwrap().uid, 1);
+    }
+
+    #[test]
+    fn layer_units() {
+        let layer: LayerInstance = serde_json::from_value(serde_json::json!({
+            "__identifier": "Collision", "__type": "IntGrid", "__cWid": 3, "__cHei": 2, "__gridSize": 8,
+            "__pxTotalOffsetX": 4, "__pxTotalOffsetY": 0, "__tilesetDefUid": null, "layerDefUid": 1,
+            "visible": true, "intGridCsv": [0, 1, 0, 2, 2, 0],
+        })).unwrap();
+        assert_eq!(layer.to_cells(glam::Vec2::new(20.0, 8.0)), glam::Vec2::new(2.0, 1.0));
+        assert_eq!(layer.to_pixels(glam::Vec2::new(2.0, 1.0)), glam::Vec2::new(20.0, 8.0));
+        let map = layer.collision(|v| v == 2);
+        assert!(map.is_solid(0, 1) && map.is_solid(1, 1) && !map.is_solid(1, 0));
+        assert_eq!(layer.int_value(1, 0), 1);
+        assert_eq!(layer.int_value(5, 0), 0);
+    }
+}
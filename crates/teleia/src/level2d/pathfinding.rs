@@ -0,0 +1,399 @@
+// Pathfinding on a grid of cells, in the same y-down tile coordinates as the rest of level2d.
+// North is up on screen, so Cardinal::offsets (which assumes y points up) is flipped in tile_offset.
+
+use crate::utils::Cardinal;
+use super::collision;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    /// Only cardinal steps
+    Four,
+    /// Cardinal and diagonal steps. Diagonals can't cut corners past impassable cells.
+    Eight,
+}
+
+/// One step between neighboring cells. A diagonal step is a pair of cardinals, e.g. North then East.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Step {
+    pub primary: Cardinal,
+    pub secondary: Option<Cardinal>,
+}
+impl Step {
+    /// Change in tile position
+    pub fn offset(&self) -> glam::IVec2 {
+        tile_offset(self.primary) + self.secondary.map(tile_offset).unwrap_or(glam::IVec2::ZERO)
+    }
+    pub fn is_diagonal(&self) -> bool { self.secondary.is_some() }
+}
+
+/// Cardinal::offsets in y-down tile coordinates
+fn tile_offset(c: Cardinal) -> glam::IVec2 {
+    let (x, y) = c.offsets();
+    glam::IVec2::new(x, -y)
+}
+
+const CARDINAL_STEPS: [Step; 4] = [
+    Step { primary: Cardinal::North, secondary: None },
+    Step { primary: Cardinal::South, secondary: None },
+    Step { primary: Cardinal::West, secondary: None },
+    Step { primary: Cardinal::East, secondary: None },
+];
+const DIAGONAL_STEPS: [Step; 4] = [
+    Step { primary: Cardinal::North, secondary: Some(Cardinal::West) },
+    Step { primary: Cardinal::North, secondary: Some(Cardinal::East) },
+    Step { primary: Cardinal::South, secondary: Some(Cardinal::West) },
+    Step { primary: Cardinal::South, secondary: Some(Cardinal::East) },
+];
+
+/// Min-heap entry for the searches below
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Open {
+    priority: f32,
+    cell: glam::IVec2,
+}
+impl Eq for Open {}
+impl Ord for Open {
+    fn cmp(&self, o: &Self) -> std::cmp::Ordering {
+        o.priority.total_cmp(&self.priority)
+    }
+}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(o)) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    /// Every cell visited, including the start and goal
+    pub cells: Vec<glam::IVec2>,
+    /// Steps to take from the start, one fewer than cells
+    pub steps: Vec<Step>,
+    pub cost: f32,
+}
+
+/// Index of a cell in a row-major grid starting at origin
+fn grid_index(origin: glam::IVec2, width: i32, height: i32, x: i32, y: i32) -> Option<usize> {
+    let (x, y) = (x - origin.x, y - origin.y);
+    if x < 0 || y < 0 || x >= width || y >= height { return None; }
+    Some((x + y * width) as usize)
+}
+
+/// Cost of entering each cell; None means impassable
+#[derive(Debug, Clone)]
+pub struct Grid {
+    /// Position of the top left cell, which can be negative for infinite maps
+    pub origin: glam::IVec2,
+    pub width: i32,
+    pub height: i32,
+    costs: Vec<Option<f32>>,
+}
+impl Grid {
+    /// Every cell passable with cost 1
+    pub fn new(width: i32, height: i32) -> Self {
+        Self::with_origin(glam::IVec2::ZERO, width, height)
+    }
+
+    /// Like new, but the top left cell is at origin rather than (0, 0)
+    pub fn with_origin(origin: glam::IVec2, width: i32, height: i32) -> Self {
+        Self { origin, width, height, costs: vec![Some(1.0); (width * height).max(0) as usize] }
+    }
+
+    /// Fully solid tiles are impassable and everything else costs 1
+    pub fn from_collision(map: &collision::CollisionMap) -> Self {
+        let mut ret = Self::with_origin(map.origin, map.width, map.height);
+        for p in ret.cells() {
+            if map.is_solid(p.x, p.y) { ret.set_cost(p.x, p.y, None); }
+        }
+        ret
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        grid_index(self.origin, self.width, self.height, x, y)
+    }
+
+    /// Position of every cell, row by row
+    pub fn cells(&self) -> impl Iterator<Item=glam::IVec2> + use<> {
+        let (origin, w) = (self.origin, self.width);
+        (0..self.height).flat_map(move |y| (0..w).map(move |x| origin + glam::IVec2::new(x, y)))
+    }
+
+    /// None for impassable cells and cells outside the grid
+    pub fn cost(&self, x: i32, y: i32) -> Option<f32> {
+        self.costs[self.index(x, y)?]
+    }
+
+    /// Costs must be positive and finite, so that searches terminate and the A* heuristic holds.
+    /// Anything else is ignored with a warning.
+    pub fn set_cost(&mut self, x: i32, y: i32, cost: Option<f32>) {
+        if let Some(c) = cost && !(c.is_finite() && c > 0.0) {
+            log::warn!("ignoring invalid path cost {} at ({}, {})", c, x, y);
+            return;
+        }
+        if let Some(i) = self.index(x, y) { self.costs[i] = cost; }
+    }
+
+    pub fn passable(&self, x: i32, y: i32) -> bool {
+        self.cost(x, y).is_some()
+    }
+
+    /// Neighbors reachable from a cell, with the step to get there and its cost
+    fn neighbors(&self, p: glam::IVec2, movement: Movement) -> impl Iterator<Item=(glam::IVec2, Step, f32)> + '_ {
+        let diagonals: &[Step] = match movement {
+            Movement::Four => &[],
+            Movement::Eight => &DIAGONAL_STEPS,
+        };
+        CARDINAL_STEPS.iter().chain(diagonals.iter()).filter_map(move |s| {
+            let n = p + s.offset();
+            let cost = self.cost(n.x, n.y)?;
+            if let Some(sec) = s.secondary {
+                let a = p + tile_offset(s.primary);
+                let b = p + tile_offset(sec);
+                if !self.passable(a.x, a.y) || !self.passable(b.x, b.y) { return None; }
+                return Some((n, *s, cost * std::f32::consts::SQRT_2));
+            }
+            Some((n, *s, cost))
+        })
+    }
+
+    /// Lower bound on the cost between two cells, for A*
+    fn heuristic(&self, a: glam::IVec2, b: glam::IVec2, movement: Movement, min_cost: f32) -> f32 {
+        let d = (a - b).abs();
+        let (lo, hi) = (d.x.min(d.y) as f32, d.x.max(d.y) as f32);
+        min_cost * match movement {
+            Movement::Four => lo + hi,
+            Movement::Eight => hi + (std::f32::consts::SQRT_2 - 1.0) * lo,
+        }
+    }
+
+    /// Cheapest path between two cells, or None if the goal can't be reached
+    pub fn astar(&self, start: glam::IVec2, goal: glam::IVec2, movement: Movement) -> Option<Path> {
+        let si = self.index(start.x, start.y)?;
+        let gi = self.index(goal.x, goal.y)?;
+        self.cost(goal.x, goal.y)?;
+        let min_cost = self.costs.iter().flatten().copied().fold(f32::INFINITY, f32::min);
+        let mut best = vec![f32::INFINITY; self.costs.len()];
+        let mut came_from: Vec<Option<(glam::IVec2, Step)>> = vec![None; self.costs.len()];
+        let mut open = std::collections::BinaryHeap::new();
+        best[si] = 0.0;
+        open.push(Open { priority: self.heuristic(start, goal, movement, min_cost), cell: start });
+        while let Some(Open { priority, cell }) = open.pop() {
+            let ci = self.index(cell.x, cell.y)?;
+            if ci == gi { break; }
+            // stale entry, the cell was pushed again after a cheaper path to it was found
+            if priority > best[ci] + self.heuristic(cell, goal, movement, min_cost) { continue; }
+            for (n, step, cost) in self.neighbors(cell, movement) {
+                let ni = self.index(n.x, n.y)?;
+                let g = best[ci] + cost;
+                if g < best[ni] {
+                    best[ni] = g;
+                    came_from[ni] = Some((cell, step));
+                    open.push(Open { priority: g + self.heuristic(n, goal, movement, min_cost), cell: n });
+                }
+            }
+        }
+        if !best[gi].is_finite() { return None; }
+        let mut cells = vec![goal];
+        let mut steps = Vec::new();
+        let mut cur = goal;
+        while cur != start {
+            let (prev, step) = came_from[self.index(cur.x, cur.y)?]?;
+            cells.push(prev);
+            steps.push(step);
+            cur = prev;
+        }
+        cells.reverse();
+        steps.reverse();
+        Some(Path { cells, steps, cost: best[gi] })
+    }
+
+    /// Distances from every cell to the nearest goal, so that any number of agents can share one search
+    pub fn flow_field(&self, goals: &[glam::IVec2], movement: Movement) -> FlowField {
+        let mut distances = vec![f32::INFINITY; self.costs.len()];
+        let mut open = std::collections::BinaryHeap::new();
+        for g in goals {
+            if let Some(i) = self.index(g.x, g.y) && self.costs[i].is_some() {
+                distances[i] = 0.0;
+                open.push(Open { priority: 0.0, cell: *g });
+            }
+        }
+        // Dijkstra outward from the goals. Moves are reversed, so the cost is that of the cell being left.
+        while let Some(Open { priority, cell }) = open.pop() {
+            let Some(ci) = self.index(cell.x, cell.y) else { continue };
+            if priority > distances[ci] { continue; }
+            let Some(leave) = self.cost(cell.x, cell.y) else { continue };
+            for (n, step, _) in self.neighbors(cell, movement) {
+                let Some(ni) = self.index(n.x, n.y) else { continue };
+                let d = distances[ci] + if step.is_diagonal() { leave * std::f32::consts::SQRT_2 } else { leave };
+                if d < distances[ni] {
+                    distances[ni] = d;
+                    open.push(Open { priority: d, cell: n });
+                }
+            }
+        }
+        // the best step is the one minimizing the step's own cost plus the distance remaining after it
+        let mut steps = vec![None; self.costs.len()];
+        for (i, p) in self.cells().enumerate() {
+            if distances[i] == 0.0 || !distances[i].is_finite() { continue; }
+            steps[i] = self.neighbors(p, movement)
+                .filter_map(|(n, step, cost)| Some((step, distances[self.index(n.x, n.y)?] + cost)))
+                .filter(|(_, d)| d.is_finite())
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(step, _)| step);
+        }
+        FlowField { origin: self.origin, width: self.width, height: self.height, distances, steps }
+    }
+}
+
+/// Result of Grid::flow_field
+#[derive(Debug, Clone)]
+pub struct FlowField {
+    pub origin: glam::IVec2,
+    pub width: i32,
+    pub height: i32,
+    distances: Vec<f32>,
+    steps: Vec<Option<Step>>,
+}
+impl FlowField {
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        grid_index(self.origin, self.width, self.height, x, y)
+    }
+    /// Which way to go from a cell to get closer to a goal.
+    /// None at a goal, or where no goal is reachable.
+    pub fn step(&self, x: i32, y: i32) -> Option<Step> {
+        self.steps[self.index(x, y)?]
+    }
+    /// Cost of the cheapest path from a cell to a goal, None if unreachable
+    pub fn distance(&self, x: i32, y: i32) -> Option<f32> {
+        Some(self.distances[self.index(x, y)?]).filter(|d| d.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: i32, y: i32) -> glam::IVec2 { glam::IVec2::new(x, y) }
+    fn step(primary: Cardinal, secondary: Option<Cardinal>) -> Step { Step { primary, secondary } }
+
+    #[test]
+    fn steps_are_y_down() {
+        assert_eq!(step(Cardinal::North, None).offset(), p(0, -1));
+        assert_eq!(step(Cardinal::South, Some(Cardinal::East)).offset(), p(1, 1));
+        assert_eq!(step(Cardinal::North, Some(Cardinal::West)).offset(), p(-1, -1));
+    }
+
+    #[test]
+    fn astar_open_grid() {
+        let g = Grid::new(5, 5);
+        let four = g.astar(p(0, 0), p(3, 2), Movement::Four).unwrap();
+        assert_eq!(four.cost, 5.0);
+        assert_eq!((four.cells.len(), four.steps.len()), (6, 5));
+        assert_eq!((four.cells[0], four.cells[5]), (p(0, 0), p(3, 2)));
+        for (i, s) in four.steps.iter().enumerate() {
+            assert_eq!(four.cells[i] + s.offset(), four.cells[i + 1]);
+        }
+        let eight = g.astar(p(0, 0), p(2, 2), Movement::Eight).unwrap();
+        assert!((eight.cost - 2.0 * std::f32::consts::SQRT_2).abs() < 1e-5);
+        assert_eq!(eight.steps, vec![step(Cardinal::South, Some(Cardinal::East)); 2]);
+    }
+
+    #[test]
+    fn astar_does_not_cut_corners() {
+        let mut g = Grid::new(2, 2);
+        g.set_cost(1, 0, None);
+        let path = g.astar(p(0, 0), p(1, 1), Movement::Eight).unwrap();
+        assert_eq!(path.cells, vec![p(0, 0), p(0, 1), p(1, 1)]);
+        assert_eq!(path.steps, vec![step(Cardinal::South, None), step(Cardinal::East, None)]);
+        assert_eq!(path.cost, 2.0);
+    }
+
+    #[test]
+    fn astar_avoids_expensive_cells() {
+        // .x.
+        // ...
+        let mut g = Grid::new(3, 2);
+        g.set_cost(1, 0, Some(10.0));
+        let path = g.astar(p(0, 0), p(2, 0), Movement::Four).unwrap();
+        assert_eq!(path.cells, vec![p(0, 0), p(0, 1), p(1, 1), p(2, 1), p(2, 0)]);
+        assert_eq!(path.cost, 4.0);
+    }
+
+    #[test]
+    fn astar_unreachable() {
+        let mut g = Grid::new(3, 1);
+        g.set_cost(1, 0, None);
+        assert_eq!(g.astar(p(0, 0), p(2, 0), Movement::Eight), None);
+        assert_eq!(g.astar(p(0, 0), p(1, 0), Movement::Eight), None);
+        assert_eq!(g.astar(p(0, 0), p(5, 0), Movement::Eight), None);
+    }
+
+    #[test]
+    fn invalid_costs_are_ignored() {
+        let mut g = Grid::new(1, 1);
+        for c in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            g.set_cost(0, 0, Some(c));
+            assert_eq!(g.cost(0, 0), Some(1.0));
+        }
+        g.set_cost(0, 0, Some(0.5));
+        assert_eq!(g.cost(0, 0), Some(0.5));
+    }
+
+    #[test]
+    fn grid_with_origin() {
+        let map = collision::CollisionMap::with_origin(p(-2, -1), 3, 1, vec![false, true, false], Vec::new());
+        let g = Grid::from_collision(&map);
+        assert_eq!(g.origin, p(-2, -1));
+        assert!(g.passable(-2, -1) && !g.passable(-1, -1) && g.passable(0, -1));
+        assert!(!g.passable(1, -1));
+        assert_eq!(g.cells().collect::<Vec<_>>(), vec![p(-2, -1), p(-1, -1), p(0, -1)]);
+        let mut open = Grid::with_origin(p(-3, -3), 4, 4);
+        open.set_cost(-2, -3, Some(2.0));
+        assert_eq!(open.astar(p(-3, -3), p(0, 0), Movement::Four).unwrap().cost, 6.0);
+    }
+
+    #[test]
+    fn flow_field_points_toward_the_goal() {
+        let g = Grid::new(3, 3);
+        let f = g.flow_field(&[p(0, 0)], Movement::Eight);
+        assert_eq!(f.step(0, 0), None);
+        assert_eq!(f.distance(0, 0), Some(0.0));
+        assert_eq!(f.step(2, 2), Some(step(Cardinal::North, Some(Cardinal::West))));
+        assert_eq!(f.step(0, 2), Some(step(Cardinal::North, None)));
+        assert!((f.distance(2, 2).unwrap() - 2.0 * std::f32::consts::SQRT_2).abs() < 1e-5);
+        assert_eq!(f.distance(3, 0), None);
+    }
+
+    #[test]
+    fn flow_field_does_not_cut_corners() {
+        let mut g = Grid::new(2, 2);
+        g.set_cost(1, 0, None);
+        let f = g.flow_field(&[p(1, 1)], Movement::Eight);
+        assert_eq!(f.step(0, 0), Some(step(Cardinal::South, None)));
+        assert_eq!(f.distance(0, 0), Some(2.0));
+        assert_eq!(f.step(1, 0), None);
+        assert_eq!(f.distance(1, 0), None);
+    }
+
+    #[test]
+    fn flow_field_steps_account_for_cost() {
+        // G  1.5  1
+        // 1  100  start
+        // West is closer to the goal once entered, but entering it costs much more than going North.
+        let mut g = Grid::new(3, 2);
+        g.set_cost(1, 0, Some(1.5));
+        g.set_cost(1, 1, Some(100.0));
+        let f = g.flow_field(&[p(0, 0)], Movement::Four);
+        assert_eq!(f.distance(1, 1), Some(2.0));
+        assert_eq!(f.distance(2, 0), Some(2.5));
+        assert_eq!(f.step(2, 1), Some(step(Cardinal::North, None)));
+        assert_eq!(f.distance(2, 1), Some(3.5));
+    }
+
+    #[test]
+    fn flow_field_multiple_goals() {
+        let g = Grid::new(5, 1);
+        let f = g.flow_field(&[p(0, 0), p(4, 0)], Movement::Four);
+        assert_eq!(f.step(1, 0), Some(step(Cardinal::West, None)));
+        assert_eq!(f.step(3, 0), Some(step(Cardinal::East, None)));
+        assert_eq!(f.distance(2, 0), Some(2.0));
+    }
+}
@@ -1,10 +1,22 @@
 #![allow(dead_code)]
 
+mod tmx;
+
 use std::collections::HashMap;
 use serde::Deserialize;
+use base64::prelude::*;
 use glow::HasContext;
+use bitflags::bitflags;
+
+use crate::{context, erm, mesh, shader, state, texture, Erm};
+use super::{collision, pathfinding};
 
-use crate::{context, erm, mesh, shader, texture, Erm};
+/// Location of the animation attribute in the tiled vertex shader
+const ATTRIB_ANIMATION: u32 = 6;
+/// Size of the animation_offsets uniform array, including the unused entry for non-animated tiles
+const MAX_ANIMATIONS: usize = 64;
+/// Width and height in tiles of the pieces LevelRenderer splits layers into
+pub const CHUNK_SIZE: i32 = 16;
 
 #[derive(Debug)]
 pub enum Err {
@@ -13,6 +25,10 @@ pub enum Err {
     GIDNotFound(u32),
     AssetNotFound(String),
     GL(String),
+    UnknownEncoding(String),
+    UnknownCompression(String),
+    MissingAttribute(String),
+    UnexpectedElement(String),
 }
 impl std::error::Error for Err {}
 impl std::fmt::Display for Err {
@@ -23,6 +39,10 @@ impl std::fmt::Display for Err {
             Self::GIDNotFound(gid) => write!(f, "GID not found: {}", gid),
             Self::AssetNotFound(ass) => write!(f, "asset not found: {}", ass),
             Self::GL(msg) => write!(f, "GL error: {msg:}"),
+            Self::UnknownEncoding(e) => write!(f, "unknown layer data encoding: {}", e),
+            Self::UnknownCompression(c) => write!(f, "unknown layer data compression: {}", c),
+            Self::MissingAttribute(a) => write!(f, "missing attribute: {}", a),
+            Self::UnexpectedElement(e) => write!(f, "unexpected element: {}", e),
         }
     }
 }
@@ -44,48 +64,516 @@ pub struct Layer {
     name: String,
     id: i32,
     #[serde(rename = "type")] ty: LayerType,
-    width: i32, height: i32,
+    /// Object layers have no dimensions or data
+    #[serde(default)] width: i32,
+    #[serde(default)] height: i32,
     x: i32, y: i32,
+    /// In pixels
+    #[serde(default)] offsetx: f32,
+    #[serde(default)] offsety: f32,
+    /// How fast the layer scrolls relative to the camera
+    #[serde(default = "default_one")] parallaxx: f32,
+    #[serde(default = "default_one")] parallaxy: f32,
     opacity: f32,
     visible: bool,
-    data: Vec<u32>,
+    #[serde(skip)] data: Vec<u32>,
+    /// Either a plain array of GIDs or an encoded string, decoded into data on load
+    #[serde(rename = "data", default)] raw_data: Option<RawData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    /// Tile data for infinite maps, used instead of data
+    #[serde(default)] chunks: Vec<Chunk>,
+    #[serde(default)] objects: Vec<Object>,
+    #[serde(default)] properties: Vec<Property>,
+}
+fn default_one() -> f32 { 1.0 }
+/// Non-empty tiles of a row-major block of GIDs starting at pos
+fn grid_tiles(pos: glam::IVec2, width: i32, height: i32, data: &[u32]) -> impl Iterator<Item=(glam::IVec2, u32)> + '_ {
+    data.iter()
+        .take((width * height).max(0) as usize)
+        .enumerate()
+        .filter(|(_, gid)| **gid != 0)
+        .map(move |(i, gid)| (pos + glam::IVec2::new(i as i32 % width, i as i32 / width), *gid))
+}
+/// Union of the bounds of the tile layers as (min, max), max exclusive; both are zero if there are no tiles
+fn tile_bounds<'a>(layers: impl Iterator<Item=&'a Layer>) -> (glam::IVec2, glam::IVec2) {
+    let (lo, hi) = layers
+        .filter(|l| matches!(l.ty, LayerType::Tile))
+        .map(|l| l.bounds())
+        .filter(|(lo, hi)| lo.cmplt(*hi).all())
+        .fold((glam::IVec2::MAX, glam::IVec2::MIN), |(lo, hi), (l, h)| (lo.min(l), hi.max(h)));
+    if lo.cmpge(hi).any() { return (glam::IVec2::ZERO, glam::IVec2::ZERO); }
+    (lo, hi)
+}
+#[derive(Debug, Deserialize)]
+pub struct Chunk {
+    x: i32, y: i32,
+    width: i32, height: i32,
+    #[serde(skip)] data: Vec<u32>,
+    #[serde(rename = "data")] raw_data: Option<RawData>,
+}
+impl Chunk {
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawData {
+    Tiles(Vec<u32>),
+    Encoded(String),
+}
+impl RawData {
+    fn decode(self, encoding: Option<&str>, compression: Option<&str>) -> Erm<Vec<u32>> {
+        match self {
+            Self::Tiles(ts) => Ok(ts),
+            Self::Encoded(s) => decode_data(&s, encoding, compression),
+        }
+    }
+}
+impl Layer {
+    fn decode(&mut self) -> Erm<()> {
+        let (enc, comp) = (self.encoding.as_deref(), self.compression.as_deref());
+        if let Some(raw) = self.raw_data.take() {
+            self.data = raw.decode(enc, comp)?;
+        }
+        for c in self.chunks.iter_mut() {
+            if let Some(raw) = c.raw_data.take() {
+                c.data = raw.decode(enc, comp)?;
+            }
+        }
+        Ok(())
+    }
+    /// GID at a tile position, 0 if there is no tile there
+    pub fn tile(&self, x: i32, y: i32) -> Erm<u32> {
+        if self.chunks.is_empty() {
+            if x < 0 || y < 0 || x >= self.width || y >= self.height { return Ok(0); }
+            return Ok(*self.data.get((x + y * self.width) as usize).ok_or(Err::LayerDataTooSmall)?);
+        }
+        match self.chunks.iter().find(|c| c.contains(x, y)) {
+            Some(c) => Ok(*c.data.get((x - c.x + (y - c.y) * c.width) as usize).ok_or(Err::LayerDataTooSmall)?),
+            None => Ok(0),
+        }
+    }
+    /// Every non-empty tile as (position, GID), from data or from the chunks of infinite maps
+    pub fn tiles(&self) -> impl Iterator<Item=(glam::IVec2, u32)> + '_ {
+        let data: &[u32] = if self.chunks.is_empty() { &self.data } else { &[] };
+        grid_tiles(glam::IVec2::ZERO, self.width, self.height, data)
+            .chain(self.chunks.iter().flat_map(|c| grid_tiles(glam::IVec2::new(c.x, c.y), c.width, c.height, &c.data)))
+    }
+    /// Tile bounds as (min, max), max exclusive. Infinite maps can extend into negative coordinates.
+    pub fn bounds(&self) -> (glam::IVec2, glam::IVec2) {
+        if self.chunks.is_empty() {
+            return (glam::IVec2::ZERO, glam::IVec2::new(self.width, self.height));
+        }
+        self.chunks.iter().fold(
+            (glam::IVec2::MAX, glam::IVec2::MIN),
+            |(lo, hi), c| (lo.min(glam::IVec2::new(c.x, c.y)), hi.max(glam::IVec2::new(c.x + c.width, c.y + c.height))),
+        )
+    }
+    /// Range of LevelRenderer chunks (inclusive) covering the layer, if it has any tiles
+    pub fn chunk_bounds(&self) -> Option<(glam::IVec2, glam::IVec2)> {
+        let (lo, hi) = self.bounds();
+        if lo.cmpge(hi).any() { return None; }
+        let size = glam::IVec2::splat(CHUNK_SIZE);
+        Some((lo.div_euclid(size), (hi - 1).div_euclid(size)))
+    }
+    pub fn parallax(&self) -> glam::Vec2 { glam::Vec2::new(self.parallaxx, self.parallaxy) }
+    /// Offset in pixels
+    pub fn offset(&self) -> glam::Vec2 { glam::Vec2::new(self.offsetx, self.offsety) }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn is_visible(&self) -> bool { self.visible }
+    pub fn objects(&self) -> &[Object] { &self.objects }
+    pub fn property(&self, name: &str) -> Option<&serde_json::Value> {
+        property(&self.properties, name)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LevelTileset {
     firstgid: i32,
-    source: String,
+    /// File the tileset is stored in, or its name if it is embedded in the level
+    #[serde(default)] source: String,
+    /// Tilesets saved inside the level rather than in their own file
+    #[serde(flatten)] embedded: Option<Tileset>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Orientation {
+    #[default]
+    #[serde(rename = "orthogonal")]
+    Orthogonal,
+    #[serde(rename = "isometric")]
+    Isometric,
+    #[serde(rename = "staggered")]
+    Staggered,
+    #[serde(rename = "hexagonal")]
+    Hexagonal,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Level {
+    #[serde(default)] orientation: Orientation,
+    /// Infinite maps store their tile layers in chunks, and width and height are only a hint
+    #[serde(default)] infinite: bool,
     width: i32, height: i32,
     tilewidth: i32, tileheight: i32,
     layers: Vec<Layer>,
     tilesets: Vec<LevelTileset>,
+    #[serde(default)] properties: Vec<Property>,
 }
+/// Decode layer data stored as CSV or base64 (optionally compressed with zlib, gzip or zstd)
+fn decode_data(data: &str, encoding: Option<&str>, compression: Option<&str>) -> Erm<Vec<u32>> {
+    use std::io::Read;
+    match encoding {
+        Some("csv") => data.split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| Ok(s.parse::<u32>()?))
+            .collect(),
+        Some("base64") => {
+            let bytes = BASE64_STANDARD.decode(data.trim())?;
+            let mut out = Vec::new();
+            match compression {
+                None | Some("") => out = bytes,
+                Some("zlib") => { flate2::read::ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut out)?; },
+                Some("gzip") => { flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut out)?; },
+                Some("zstd") => { ruzstd::decoding::StreamingDecoder::new(bytes.as_slice())?.read_to_end(&mut out)?; },
+                Some(c) => return erm(Err::UnknownCompression(c.to_string())),
+            }
+            Ok(out.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+        },
+        Some(e) => erm(Err::UnknownEncoding(e.to_string())),
+        None => erm(Err::UnknownEncoding("none".to_string())),
+    }
+}
+
 impl Level {
+    /// Load a map saved as JSON or TMX
     pub fn new(bytes: &str) -> Erm<Self> {
-        Ok(serde_json::from_str(bytes)?)
+        if bytes.trim_start().starts_with('<') { return tmx::level(bytes); }
+        let mut ret: Self = serde_json::from_str(bytes)?;
+        for l in ret.layers.iter_mut() { l.decode()?; }
+        for lts in ret.tilesets.iter_mut() {
+            if let Some(ts) = &lts.embedded && lts.source.is_empty() { lts.source = ts.name.clone(); }
+        }
+        Ok(ret)
+    }
+    /// A tileset saved inside the level, by name (which is also its source in resolve_gid)
+    pub fn embedded_tileset(&self, name: &str) -> Option<&Tileset> {
+        self.tilesets.iter().filter_map(|lts| lts.embedded.as_ref()).find(|ts| ts.name == name)
+    }
+
+    pub fn property(&self, name: &str) -> Option<&serde_json::Value> {
+        property(&self.properties, name)
+    }
+    pub fn is_infinite(&self) -> bool { self.infinite }
+    /// Split a GID into the tile's index within its tileset, the tileset's source, and flip flags
+    pub fn resolve_gid(&self, gid: u32) -> Erm<(i32, &str, Flip)> {
+        let offset = (gid & !Flip::all().bits()) as i32;
+        for lts in self.tilesets.iter().rev() {
+            if lts.firstgid <= offset {
+                return Ok((offset - lts.firstgid, &lts.source, Flip::from_bits_truncate(gid)));
+            }
+        }
+        erm(Err::GIDNotFound(gid))
+    }
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+    /// Every object in every object layer
+    pub fn objects(&self) -> impl Iterator<Item=&Object> {
+        self.layers.iter().flat_map(|l| l.objects.iter())
+    }
+    pub fn find_object(&self, name: &str) -> Option<&Object> {
+        self.objects().find(|o| o.name == name)
+    }
+    pub fn objects_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a Object> + 'a {
+        self.objects().filter(move |o| o.name == name)
+    }
+    /// Objects with the given type (called class in newer versions of Tiled), e.g. "spawn" or "trigger"
+    pub fn objects_of_type<'a>(&'a self, ty: &'a str) -> impl Iterator<Item=&'a Object> + 'a {
+        self.objects().filter(move |o| o.ty == ty)
+    }
+    /// Convert a position in pixels (as objects use) to tile units
+    pub fn to_tile_units(&self, v: glam::Vec2) -> glam::Vec2 {
+        v / glam::Vec2::new(self.tilewidth as f32, self.tileheight as f32)
+    }
+    /// An object's position in tile units
+    pub fn object_position(&self, o: &Object) -> glam::Vec2 {
+        self.to_tile_units(o.position())
+    }
+    /// An object's bounding box in tile units, e.g. for testing triggers against the player
+    pub fn object_rect(&self, o: &Object) -> collision::Rect {
+        let (min, max) = o.bounds();
+        collision::Rect { min: self.to_tile_units(min), max: self.to_tile_units(max) }
+    }
+
+    /// Tile bounds of all tile layers combined as (min, max), max exclusive.
+    /// For infinite maps this comes from the chunks, since width and height are only a hint.
+    pub fn tile_bounds(&self) -> (glam::IVec2, glam::IVec2) {
+        tile_bounds(self.layers.iter())
+    }
+
+    /// Collision for a single tile layer, covering that layer's bounds.
+    /// A tile is fully solid if its tileset gives it a true bool property with the given name;
+    /// otherwise any collision shapes drawn for it in the tileset are used (as bounding boxes).
+    pub fn layer_collision(&self, assets: &Assets, lidx: usize, property: &str) -> Erm<collision::CollisionMap> {
+        let layer = self.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
+        self.layers_collision(assets, std::slice::from_ref(layer), property)
+    }
+
+    /// Collision for all tile layers combined, see layer_collision
+    pub fn collision(&self, assets: &Assets, property: &str) -> Erm<collision::CollisionMap> {
+        self.layers_collision(assets, &self.layers, property)
+    }
+
+    fn layers_collision(&self, assets: &Assets, layers: &[Layer], property: &str) -> Erm<collision::CollisionMap> {
+        let (lo, hi) = tile_bounds(layers.iter());
+        let dims = hi - lo;
+        let mut solid = vec![false; (dims.x * dims.y) as usize];
+        let mut shapes = Vec::new();
+        for layer in layers.iter().filter(|l| matches!(l.ty, LayerType::Tile)) {
+            for (p, gid) in layer.tiles() {
+                let (lid, ass, flip) = assets.lookup_gid(self, gid)?;
+                let cell = (p.x - lo.x + (p.y - lo.y) * dims.x) as usize;
+                if ass.tileset.tile_property(lid, property) == Some(&serde_json::Value::Bool(true)) {
+                    solid[cell] = true;
+                    continue;
+                }
+                for r in ass.tileset.tile_shapes(lid) {
+                    let r = flip.apply_rect(r);
+                    // a shape covering the whole tile can be merged with its neighbors
+                    if r.min.cmple(glam::Vec2::ZERO).all() && r.max.cmpge(glam::Vec2::ONE).all() {
+                        solid[cell] = true;
+                    } else {
+                        shapes.push(r.translate(p.as_vec2()));
+                    }
+                }
+            }
+        }
+        Ok(collision::CollisionMap::with_origin(lo, dims.x, dims.y, solid, shapes))
+    }
+
+    /// Pathfinding grid covering the same tiles as collision. Tiles that are fully solid are impassable,
+    /// and tiles with a numeric cost_property cost that much to enter instead of 1.
+    /// Where several layers set a cost, the topmost one wins. Costs that aren't positive are ignored.
+    pub fn path_grid(&self, assets: &Assets, solid_property: &str, cost_property: &str) -> Erm<pathfinding::Grid> {
+        let mut ret = pathfinding::Grid::from_collision(&self.collision(assets, solid_property)?);
+        for layer in self.layers.iter().filter(|l| matches!(l.ty, LayerType::Tile)) {
+            for (p, gid) in layer.tiles() {
+                if !ret.passable(p.x, p.y) { continue; }
+                let (lid, ass, _) = assets.lookup_gid(self, gid)?;
+                if let Some(cost) = ass.tileset.tile_property(lid, cost_property).and_then(|v| v.as_f64()) {
+                    ret.set_cost(p.x, p.y, Some(cost as f32));
+                }
+            }
+        }
+        Ok(ret)
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// A custom property set in the Tiled editor
+#[derive(Debug, Clone, Deserialize)]
+pub struct Property {
+    pub name: String,
+    #[serde(rename = "type", default)] pub ty: String,
+    pub value: serde_json::Value,
+}
+pub fn property<'a>(props: &'a [Property], name: &str) -> Option<&'a serde_json::Value> {
+    props.iter().find(|p| p.name == name).map(|p| &p.value)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Point {
+    pub x: f32, pub y: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Text {
+    pub text: String,
+    #[serde(default)] pub wrap: bool,
+    /// e.g. "#ff0000"
+    pub color: Option<String>,
+    pub pixelsize: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shape<'a> {
+    Rectangle,
+    Ellipse,
+    Point,
+    Polygon(&'a [Point]),
+    Polyline(&'a [Point]),
+    /// A tile placed as an object; the GID can be looked up with Assets::lookup_gid
+    Tile(u32),
+    Text(&'a Text),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Object {
+    pub id: i32,
+    #[serde(default)] pub name: String,
+    #[serde(rename = "type", alias = "class", default)] pub ty: String,
+    #[serde(default = "default_true")] pub visible: bool,
+    #[serde(default)] pub properties: Vec<Property>,
+    pub x: f32, pub y: f32,
+    #[serde(default)] pub width: f32,
+    #[serde(default)] pub height: f32,
+    /// Degrees clockwise around (x, y)
+    #[serde(default)] pub rotation: f32,
+    #[serde(default)] pub ellipse: bool,
+    #[serde(default)] pub point: bool,
+    /// Points relative to (x, y)
+    pub polygon: Option<Vec<Point>>,
+    pub polyline: Option<Vec<Point>>,
+    pub gid: Option<u32>,
+    pub text: Option<Text>,
+}
+fn default_true() -> bool { true }
+impl Object {
+    pub fn shape(&self) -> Shape<'_> {
+        if let Some(gid) = self.gid { Shape::Tile(gid) }
+        else if let Some(t) = &self.text { Shape::Text(t) }
+        else if let Some(ps) = &self.polygon { Shape::Polygon(ps) }
+        else if let Some(ps) = &self.polyline { Shape::Polyline(ps) }
+        else if self.point { Shape::Point }
+        else if self.ellipse { Shape::Ellipse }
+        else { Shape::Rectangle }
+    }
+    pub fn property(&self, name: &str) -> Option<&serde_json::Value> {
+        property(&self.properties, name)
+    }
+    /// Position in pixels
+    pub fn position(&self) -> glam::Vec2 {
+        glam::Vec2::new(self.x, self.y)
+    }
+    /// Whether a point in pixels is inside the object's bounding box
+    pub fn contains(&self, p: glam::Vec2) -> bool {
+        let (min, max) = self.bounds();
+        p.cmpge(min).all() && p.cmplt(max).all()
+    }
+    /// Axis-aligned bounding box (min, max) in pixels
+    pub fn bounds(&self) -> (glam::Vec2, glam::Vec2) {
+        let origin = glam::Vec2::new(self.x, self.y);
+        // tile objects are anchored at their bottom left corner
+        let top = if self.gid.is_some() { -self.height } else { 0.0 };
+        let local: Vec<glam::Vec2> = match self.polygon.as_ref().or(self.polyline.as_ref()) {
+            Some(ps) => ps.iter().map(|p| glam::Vec2::new(p.x, p.y)).collect(),
+            None => vec![
+                glam::Vec2::new(0.0, top),
+                glam::Vec2::new(self.width, top),
+                glam::Vec2::new(self.width, top + self.height),
+                glam::Vec2::new(0.0, top + self.height),
+            ],
+        };
+        let rot = glam::Vec2::from_angle(self.rotation.to_radians());
+        local.into_iter()
+            .map(|p| origin + rot.rotate(p))
+            .fold((glam::Vec2::MAX, glam::Vec2::MIN), |(lo, hi), p| (lo.min(p), hi.max(p)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObjectGroup {
+    objects: Vec<Object>,
+}
+
+/// Per-tile data from the tileset, only present for tiles that have some
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tile {
+    id: i32,
+    #[serde(default)] properties: Vec<Property>,
+    /// Collision shapes drawn in Tiled's tile collision editor
+    objectgroup: Option<ObjectGroup>,
+    #[serde(default)] animation: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Frame {
+    pub tileid: i32,
+    /// Milliseconds
+    pub duration: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Tileset {
     name: String,
     imagewidth: i32, imageheight: i32,
     tilewidth: i32, tileheight: i32,
     margin: i32, spacing: i32,
+    #[serde(default)] tiles: Vec<Tile>,
 }
 impl Tileset {
+    /// Load a tileset saved as JSON or TSX
     pub fn new(bytes: &str) -> Erm<Self> {
+        if bytes.trim_start().starts_with('<') { return tmx::tileset(bytes); }
         Ok(serde_json::from_str(bytes)?)
     }
+    pub fn tile(&self, id: i32) -> Option<&Tile> {
+        self.tiles.iter().find(|t| t.id == id)
+    }
+    pub fn tile_animation(&self, id: i32) -> &[Frame] {
+        self.tile(id).map(|t| t.animation.as_slice()).unwrap_or_default()
+    }
+    /// Texture coordinates of a tile's corner in the tileset image
+    fn tile_uv(&self, id: i32) -> glam::Vec2 {
+        let cols = self.imagewidth / self.tilewidth;
+        let rows = self.imageheight / self.tileheight;
+        glam::Vec2::new((id % cols) as f32 / cols as f32, (id / cols) as f32 / rows as f32)
+    }
+    pub fn tile_property(&self, id: i32, name: &str) -> Option<&serde_json::Value> {
+        property(&self.tile(id)?.properties, name)
+    }
+    /// Collision shapes of a tile, in tile units relative to the tile's top left corner
+    pub fn tile_shapes(&self, id: i32) -> Vec<collision::Rect> {
+        let scale = glam::Vec2::new(self.tilewidth as f32, self.tileheight as f32);
+        self.tile(id).and_then(|t| t.objectgroup.as_ref())
+            .map(|og| og.objects.iter()
+                 .filter(|o| !o.point)
+                 .map(|o| {
+                     let (min, max) = o.bounds();
+                     collision::Rect { min: min / scale, max: max / scale }
+                 })
+                 .collect())
+            .unwrap_or_default()
+    }
 }
 
-// TODO
-pub enum Flip {
-    None,
+bitflags! {
+    /// Stored in the high bits of a GID.
+    /// Tiled applies them to the tile image in the order diagonal, horizontal, vertical.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Flip: u32 {
+        const HORIZONTAL = 0x80000000;
+        /// Rotate 60 degrees clockwise on hexagonal maps instead
+        const DIAGONAL   = 0x20000000;
+        const VERTICAL   = 0x40000000;
+        /// Only used on hexagonal maps
+        const ROTATE_120 = 0x10000000;
+    }
+}
+impl Flip {
+    /// Where a point in the displayed tile (0 to 1, y down) comes from in the tile image, on orthogonal maps
+    pub fn sample(&self, p: glam::Vec2) -> glam::Vec2 {
+        let mut p = p;
+        if self.contains(Self::VERTICAL) { p.y = 1.0 - p.y; }
+        if self.contains(Self::HORIZONTAL) { p.x = 1.0 - p.x; }
+        if self.contains(Self::DIAGONAL) { p = glam::Vec2::new(p.y, p.x); }
+        p
+    }
+    /// Where a point in the tile image (0 to 1, y down) ends up in the displayed tile, on orthogonal maps
+    pub fn apply(&self, p: glam::Vec2) -> glam::Vec2 {
+        let mut p = p;
+        if self.contains(Self::DIAGONAL) { p = glam::Vec2::new(p.y, p.x); }
+        if self.contains(Self::HORIZONTAL) { p.x = 1.0 - p.x; }
+        if self.contains(Self::VERTICAL) { p.y = 1.0 - p.y; }
+        p
+    }
+    pub fn apply_rect(&self, r: collision::Rect) -> collision::Rect {
+        let (a, b) = (self.apply(r.min), self.apply(r.max));
+        collision::Rect { min: a.min(b), max: a.max(b) }
+    }
 }
 
 pub struct Asset {
@@ -108,29 +596,45 @@ impl Assets {
         }
         Ok(())
     }
-    pub fn lookup_gid(&self, level: &Level, gid: u32) -> Erm<(i32, &Asset, Flip)> {
-        let offset = (gid & 0x0fffffff) as i32;
-        for lts in level.tilesets.iter().rev() {
-            if lts.firstgid <= offset {
-                return Ok((
-                    offset - lts.firstgid,
-                    self.entries.get(&lts.source).ok_or(Err::AssetNotFound(lts.source.clone()))?,
-                    Flip::None
-                ))
-            }
+    /// Like load, for a tileset embedded in the level rather than stored in its own file
+    pub fn load_embedded(&mut self, ctx: &context::Context, level: &Level, nm: &str, img: &[u8]) -> Erm<()> {
+        let tileset = level.embedded_tileset(nm).ok_or(Err::AssetNotFound(nm.to_string()))?.clone();
+        let ass = Asset {
+            tileset,
+            texture: texture::Texture::new(ctx, img),
+        };
+        if self.entries.insert(nm.to_string(), ass).is_some() {
+            log::warn!("duplicate tileset entry named: {}", nm);
         }
-        erm(Err::GIDNotFound(gid))
+        Ok(())
+    }
+    pub fn lookup_gid(&self, level: &Level, gid: u32) -> Erm<(i32, &Asset, Flip)> {
+        let (lid, source, flip) = level.resolve_gid(gid)?;
+        Ok((lid, self.get(source)?, flip))
+    }
+    pub fn get(&self, source: &str) -> Erm<&Asset> {
+        Ok(self.entries.get(source).ok_or(Err::AssetNotFound(source.to_string()))?)
     }
 }
 
-pub struct LayerRenderer {
+/// Geometry for one CHUNK_SIZE by CHUNK_SIZE square of a layer
+pub struct ChunkRenderer {
     pub vao: glow::VertexArray,
     pub vertex_buf: glow::Buffer,
     pub texcoords_buf: glow::Buffer,
+    pub animation_buf: glow::Buffer,
     pub index_buf: glow::Buffer,
     pub index_count: usize,
+    /// Ranges of the index buffer that share a tileset texture
+    pub batches: Vec<Batch>,
 }
-impl LayerRenderer {
+pub struct Batch {
+    /// Tileset source, as named in the level
+    pub source: String,
+    pub start: usize,
+    pub count: usize,
+}
+impl ChunkRenderer {
     pub fn new(ctx: &context::Context) -> Erm<Self> {
         unsafe {
             let vao = ctx.gl.create_vertex_array().map_err(Err::GL)?;
@@ -143,27 +647,179 @@ impl LayerRenderer {
             ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(texcoords_buf));
             ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_TEXCOORD, 2, glow::FLOAT, false, 0, 0);
             ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_TEXCOORD);
+            let animation_buf = ctx.gl.create_buffer().map_err(Err::GL)?;
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(animation_buf));
+            ctx.gl.vertex_attrib_pointer_f32(ATTRIB_ANIMATION, 1, glow::FLOAT, false, 0, 0);
+            ctx.gl.enable_vertex_attrib_array(ATTRIB_ANIMATION);
             let index_buf = ctx.gl.create_buffer().map_err(Err::GL)?;
             ctx.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(index_buf));
             Ok(Self {
                 vao,
                 vertex_buf,
                 texcoords_buf,
+                animation_buf,
                 index_buf,
                 index_count: 0,
+                batches: Vec::new(),
             })
         }
     }
+    pub fn delete(&self, ctx: &context::Context) {
+        unsafe {
+            ctx.gl.delete_buffer(self.vertex_buf);
+            ctx.gl.delete_buffer(self.texcoords_buf);
+            ctx.gl.delete_buffer(self.animation_buf);
+            ctx.gl.delete_buffer(self.index_buf);
+            ctx.gl.delete_vertex_array(self.vao);
+        }
+    }
+    /// Replace the geometry. Each batch is a tileset source and the indices of the triangles using it.
+    pub fn upload(
+        &mut self,
+        ctx: &context::Context,
+        vertices: &[glam::Vec2],
+        texcoords: &[glam::Vec2],
+        animations: &[f32],
+        batches: Vec<(&str, Vec<u32>)>,
+    ) {
+        let mut indices = Vec::new();
+        self.batches.clear();
+        for (source, bis) in batches {
+            self.batches.push(Batch { source: source.to_string(), start: indices.len(), count: bis.len() });
+            indices.extend(bis);
+        }
+        let index_bytes: Vec<u8> = indices.iter().flat_map(|x| x.to_ne_bytes()).collect();
+        unsafe {
+            ctx.gl.bind_vertex_array(Some(self.vao));
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vertex_buf));
+            ctx.gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                std::slice::from_raw_parts(
+                    vertices.as_ptr() as _,
+                    vertices.len() * std::mem::size_of::<f32>() * 2,
+                ),
+                glow::STATIC_DRAW,
+            );
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.texcoords_buf));
+            ctx.gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                std::slice::from_raw_parts(
+                    texcoords.as_ptr() as _,
+                    texcoords.len() * std::mem::size_of::<f32>() * 2,
+                ),
+                glow::STATIC_DRAW,
+            );
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.animation_buf));
+            ctx.gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                std::slice::from_raw_parts(
+                    animations.as_ptr() as _,
+                    animations.len() * std::mem::size_of::<f32>(),
+                ),
+                glow::STATIC_DRAW,
+            );
+            ctx.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.index_buf));
+            ctx.gl.buffer_data_u8_slice(
+                glow::ELEMENT_ARRAY_BUFFER,
+                &index_bytes,
+                glow::STATIC_DRAW,
+            );
+        }
+        self.index_count = indices.len();
+    }
+    /// Draw each batch with the texture for its source
+    pub fn draw<'a, F>(&self, ctx: &context::Context, texture: F) -> Erm<()>
+    where F: Fn(&str) -> Erm<&'a texture::Texture> {
+        if self.batches.is_empty() { return Ok(()); }
+        unsafe {
+            ctx.gl.bind_vertex_array(Some(self.vao));
+        }
+        for b in self.batches.iter() {
+            texture(&b.source)?.bind(ctx);
+            unsafe {
+                ctx.gl.draw_elements(
+                    glow::TRIANGLES,
+                    b.count as _,
+                    glow::UNSIGNED_INT,
+                    (b.start * std::mem::size_of::<u32>()) as _,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+/// The populated chunks of a layer, keyed by chunk coordinates
+#[derive(Default)]
+pub struct LayerRenderer {
+    pub chunks: HashMap<glam::IVec2, ChunkRenderer>,
+}
+/// An animated tile, stored as offsets from the first frame's texture coordinates
+pub struct Animation {
+    pub source: String,
+    pub tile: i32,
+    pub frames: Vec<(glam::Vec2, u32)>,
+    pub duration: u32,
+}
+impl Animation {
+    /// Offset of the frame showing at the given time
+    pub fn offset(&self, ms: u64) -> glam::Vec2 {
+        if self.duration == 0 { return glam::Vec2::ZERO; }
+        let mut t = (ms % self.duration as u64) as u32;
+        for (off, d) in self.frames.iter() {
+            if t < *d { return *off; }
+            t -= d;
+        }
+        glam::Vec2::ZERO
+    }
 }
+/// Index of a tile's animation (1-based, 0 if it isn't animated), adding it if it's new
+fn animation_index(animations: &mut Vec<Animation>, source: &str, tileset: &Tileset, tile: i32) -> usize {
+    let frames = tileset.tile_animation(tile);
+    if frames.is_empty() { return 0; }
+    if let Some(i) = animations.iter().position(|a| a.source == source && a.tile == tile) {
+        return i + 1;
+    }
+    if animations.len() + 1 >= MAX_ANIMATIONS {
+        log::warn!("too many animated tiles, not animating tile {} in {}", tile, source);
+        return 0;
+    }
+    let base = tileset.tile_uv(tile);
+    animations.push(Animation {
+        source: source.to_string(),
+        tile,
+        frames: frames.iter().map(|f| (tileset.tile_uv(f.tileid) - base, f.duration)).collect(),
+        duration: frames.iter().map(|f| f.duration).sum(),
+    });
+    animations.len()
+}
+
+/// Range of chunks (inclusive) within bounds that overlap a view centered on a tile position
+fn chunks_in_view(
+    center: glam::Vec2,
+    half: glam::Vec2,
+    (blo, bhi): (glam::IVec2, glam::IVec2),
+) -> Option<(glam::IVec2, glam::IVec2)> {
+    let lo = (center - half).floor().as_ivec2().div_euclid(glam::IVec2::splat(CHUNK_SIZE)).max(blo);
+    let hi = (center + half).ceil().as_ivec2().div_euclid(glam::IVec2::splat(CHUNK_SIZE)).min(bhi);
+    if lo.cmpgt(hi).any() { return None; }
+    Some((lo, hi))
+}
+
 pub struct LevelRenderer {
     pub layers: Vec<LayerRenderer>,
     pub shader: shader::Shader,
+    pub animations: Vec<Animation>,
+    /// Tile position shown at the center of the screen (before parallax)
+    pub camera: glam::Vec2,
 }
 impl LevelRenderer {
     pub fn new(ctx: &context::Context, level: &Level) -> Erm<Self> {
+        if level.orientation != Orientation::Orthogonal {
+            log::warn!("only orthogonal maps are supported, drawing a {:?} map as orthogonal", level.orientation);
+        }
         let mut layers = Vec::new();
         for _ in level.layers.iter() {
-            layers.push(LayerRenderer::new(ctx)?);
+            layers.push(LayerRenderer::default());
         }
         let shader = shader::Shader::new_nolib(
             ctx,
@@ -173,26 +829,32 @@ impl LevelRenderer {
         Ok(Self {
             layers,
             shader,
+            animations: Vec::new(),
+            camera: glam::Vec2::ZERO,
         })
     }
-    pub fn populate_layer(
+    /// Build the geometry for one chunk of a layer, replacing any existing geometry
+    pub fn populate_chunk(
         &mut self,
         ctx: &context::Context,
         assets: &Assets,
         level: &Level,
-        lidx: usize
+        lidx: usize,
+        chunk: glam::IVec2,
     ) -> Erm<()> {
         let lr = self.layers.get_mut(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
         let layer = level.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
         let mut vertices = Vec::new();
         let mut texcoords = Vec::new();
-        let mut indices = Vec::new();
-        for y in 0..layer.height {
-            for x in 0..layer.width {
-                let idx = x as usize + (y * layer.width) as usize;
-                let gid = *layer.data.get(idx).ok_or(Err::LayerDataTooSmall)?;
+        let mut animations = Vec::new();
+        let mut batch_indices: Vec<(&str, Vec<u32>)> = Vec::new();
+        let base = chunk * CHUNK_SIZE;
+        for y in base.y..base.y + CHUNK_SIZE {
+            for x in base.x..base.x + CHUNK_SIZE {
+                let gid = layer.tile(x, y)?;
                 if gid == 0 { continue; }
-                let (lid, ass, _) = assets.lookup_gid(level, gid)?;
+                let (lid, source, flip) = level.resolve_gid(gid)?;
+                let ass = assets.get(source)?;
                 let cols = ass.tileset.imagewidth / ass.tileset.tilewidth;
                 let rows = ass.tileset.imageheight / ass.tileset.tileheight;
                 let col = lid % cols;
@@ -200,89 +862,119 @@ impl LevelRenderer {
                 let twidth = 1.0 / cols as f32;
                 let theight = 1.0 / rows as f32;
 
+                let anim = animation_index(&mut self.animations, source, &ass.tileset, lid) as f32;
                 let i = vertices.len() as u32;
-                let v = glam::Vec2::new(x as _, y as _);
-                vertices.push(v);
-                vertices.push(v + glam::Vec2::new(1.0, 0.0));
-                vertices.push(v + glam::Vec2::new(1.0, 1.0));
-                vertices.push(v + glam::Vec2::new(0.0, 1.0));
+                let pos = glam::Vec2::new(x as f32, y as f32);
                 let uvbase = glam::Vec2::new(col as f32 / cols as f32, row as f32 / rows as f32);
-                texcoords.push(uvbase + glam::Vec2::new(0.0, theight));
-                texcoords.push(uvbase + glam::Vec2::new(twidth, theight));
-                texcoords.push(uvbase + glam::Vec2::new(twidth, 0.0));
-                texcoords.push(uvbase);
+                for corner in [
+                    glam::Vec2::new(0.0, 0.0),
+                    glam::Vec2::new(1.0, 0.0),
+                    glam::Vec2::new(1.0, 1.0),
+                    glam::Vec2::new(0.0, 1.0),
+                ] {
+                    vertices.push(pos + corner);
+                    let s = flip.sample(corner);
+                    texcoords.push(uvbase + glam::Vec2::new(s.x * twidth, (1.0 - s.y) * theight));
+                    animations.push(anim);
+                }
+                let bidx = match batch_indices.iter().position(|(src, _)| *src == source) {
+                    Some(b) => b,
+                    None => { batch_indices.push((source, Vec::new())); batch_indices.len() - 1 }
+                };
+                let indices = &mut batch_indices[bidx].1;
                 indices.push(i); indices.push(i + 1); indices.push(i + 2);
                 indices.push(i); indices.push(i + 3); indices.push(i + 2);
             }
         }
-        let index_bytes: Vec<u8> = indices.iter().flat_map(|x| x.to_ne_bytes()).collect();
-        unsafe {
-            ctx.gl.bind_vertex_array(Some(lr.vao));
-            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(lr.vertex_buf));
-            ctx.gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                std::slice::from_raw_parts(
-                    vertices.as_ptr() as _,
-                    vertices.len() * std::mem::size_of::<f32>() * 2,
-                ),
-                glow::STATIC_DRAW,
-            );
-            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(lr.texcoords_buf));
-            ctx.gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                std::slice::from_raw_parts(
-                    texcoords.as_ptr() as _,
-                    texcoords.len() * std::mem::size_of::<f32>() * 2,
-                ),
-                glow::STATIC_DRAW,
-            );
-            ctx.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(lr.index_buf));
-            ctx.gl.buffer_data_u8_slice(
-                glow::ELEMENT_ARRAY_BUFFER,
-                &index_bytes,
-                glow::STATIC_DRAW,
-            );
-            lr.index_count = indices.len();
+        let cr = match lr.chunks.entry(chunk) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => e.insert(ChunkRenderer::new(ctx)?),
+        };
+        cr.upload(ctx, &vertices, &texcoords, &animations, batch_indices);
+        Ok(())
+    }
+    /// Build the geometry for every chunk of a layer.
+    /// Alternatively, stream populates just the chunks around the camera.
+    pub fn populate_layer(
+        &mut self,
+        ctx: &context::Context,
+        assets: &Assets,
+        level: &Level,
+        lidx: usize
+    ) -> Erm<()> {
+        let layer = level.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
+        if !matches!(layer.ty, LayerType::Tile) { return Ok(()); }
+        let Some((lo, hi)) = layer.chunk_bounds() else { return Ok(()); };
+        for cy in lo.y..=hi.y {
+            for cx in lo.x..=hi.x {
+                self.populate_chunk(ctx, assets, level, lidx, glam::IVec2::new(cx, cy))?;
+            }
         }
         Ok(())
     }
-    pub fn render_layer(
-        &self,
+    /// Tile position in a layer that is shown at the center of the screen
+    fn layer_origin(&self, level: &Level, layer: &Layer) -> glam::Vec2 {
+        self.camera * layer.parallax() - level.to_tile_units(layer.offset())
+    }
+    /// Range of chunks (inclusive) of a layer that are at least partly on screen
+    pub fn visible_chunks(&self, ctx: &context::Context, level: &Level, lidx: usize) -> Option<(glam::IVec2, glam::IVec2)> {
+        let layer = level.layers.get(lidx)?;
+        let half = level.to_tile_units(glam::Vec2::new(ctx.render_width, ctx.render_height)) / 2.0;
+        chunks_in_view(self.layer_origin(level, layer), half, layer.chunk_bounds()?)
+    }
+    /// Populate the visible chunks of a layer that aren't yet, and free chunks that are well off screen
+    pub fn stream_layer(
+        &mut self,
         ctx: &context::Context,
         assets: &Assets,
         level: &Level,
         lidx: usize,
     ) -> Erm<()> {
         let layer = level.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
-        let lr = self.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
-        // TODO: handle layers with multiple textures
-        let gid = *layer.data.iter().find(|g| **g > 0).unwrap();
-        let (_, ass, _) = assets.lookup_gid(level, gid)?;
-        ass.texture.bind(ctx);
-        unsafe {
-            ctx.gl.bind_vertex_array(Some(lr.vao));
-            ctx.gl.draw_elements(glow::TRIANGLES, lr.index_count as _, glow::UNSIGNED_INT, 0);
+        if !matches!(layer.ty, LayerType::Tile) { return Ok(()); }
+        let visible = self.visible_chunks(ctx, level, lidx);
+        let lr = self.layers.get_mut(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
+        lr.chunks.retain(|c, cr| {
+            let keep = visible.is_some_and(|(lo, hi)| c.cmpge(lo - 1).all() && c.cmple(hi + 1).all());
+            if !keep { cr.delete(ctx); }
+            keep
+        });
+        let Some((lo, hi)) = visible else { return Ok(()); };
+        for cy in lo.y..=hi.y {
+            for cx in lo.x..=hi.x {
+                let c = glam::IVec2::new(cx, cy);
+                if !self.layers[lidx].chunks.contains_key(&c) {
+                    self.populate_chunk(ctx, assets, level, lidx, c)?;
+                }
+            }
         }
         Ok(())
     }
-    pub fn populate(
+    /// Stream every layer around the camera, see stream_layer.
+    /// Call this before rendering instead of populate for large or infinite maps.
+    pub fn stream(
         &mut self,
         ctx: &context::Context,
         assets: &Assets,
         level: &Level,
     ) -> Erm<()> {
         for lidx in 0..level.layers.len() {
-            self.populate_layer(ctx, assets, level, lidx)?;
+            self.stream_layer(ctx, assets, level, lidx)?;
         }
         Ok(())
     }
-    pub fn render(
+    /// Draw the visible chunks of a layer that have been populated
+    pub fn render_layer(
         &self,
         ctx: &context::Context,
         assets: &Assets,
         level: &Level,
+        lidx: usize,
     ) -> Erm<()> {
-        self.shader.bind(ctx);
+        let layer = level.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
+        if !matches!(layer.ty, LayerType::Tile) { return Ok(()); }
+        let lr = self.layers.get(lidx).ok_or(Err::LayerIndexOutOfBounds)?;
+        let Some((lo, hi)) = self.visible_chunks(ctx, level, lidx) else { return Ok(()); };
         let sx = 2.0 * level.tilewidth as f32 / ctx.render_width;
         let sy = 2.0 * level.tileheight as f32 / ctx.render_height;
         self.shader.set_mat4(
@@ -291,11 +983,246 @@ impl LevelRenderer {
                 glam::Vec3::new(sx, -sy, 1.0),
                 glam::Quat::IDENTITY,
                 glam::Vec3::new(0.0, 0.0, 0.0),
-            ),
+            ).mul_mat4(&glam::Mat4::from_translation(-self.layer_origin(level, layer).extend(0.0))),
         );
+        for cy in lo.y..=hi.y {
+            for cx in lo.x..=hi.x {
+                if let Some(cr) = lr.chunks.get(&glam::IVec2::new(cx, cy)) {
+                    cr.draw(ctx, |src| Ok(&assets.get(src)?.texture))?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Build the geometry for every layer, starting over with no animations
+    pub fn populate(
+        &mut self,
+        ctx: &context::Context,
+        assets: &Assets,
+        level: &Level,
+    ) -> Erm<()> {
+        for lr in self.layers.iter_mut() {
+            for cr in lr.chunks.values() { cr.delete(ctx); }
+            lr.chunks.clear();
+        }
+        self.animations.clear();
+        for lidx in 0..level.layers.len() {
+            self.populate_layer(ctx, assets, level, lidx)?;
+        }
+        Ok(())
+    }
+    /// Upload the current frame of every animated tile, timed by the game's tick
+    pub fn animate(&self, ctx: &context::Context, st: &state::State) {
+        let ms = (st.tick as f64 * 1000.0 / st.tick_rate) as u64;
+        let offsets: Vec<glam::Vec2> = std::iter::once(glam::Vec2::ZERO)
+            .chain(self.animations.iter().map(|a| a.offset(ms)))
+            .collect();
+        self.shader.set_vec2_array(ctx, "animation_offsets[0]", &offsets);
+    }
+    /// Draw every layer with animated tiles showing their first frame
+    pub fn render(
+        &self,
+        ctx: &context::Context,
+        assets: &Assets,
+        level: &Level,
+    ) -> Erm<()> {
+        self.shader.bind(ctx);
+        self.shader.set_vec2_array(ctx, "animation_offsets[0]", &vec![glam::Vec2::ZERO; self.animations.len() + 1]);
+        for lidx in 0..level.layers.len() {
+            self.render_layer(ctx, assets, level, lidx)?;
+        }
+        Ok(())
+    }
+    /// Draw every layer with animated tiles at their current frame
+    pub fn render_animated(
+        &self,
+        ctx: &context::Context,
+        st: &state::State,
+        assets: &Assets,
+        level: &Level,
+    ) -> Erm<()> {
+        self.shader.bind(ctx);
+        self.animate(ctx, st);
         for lidx in 0..level.layers.len() {
             self.render_layer(ctx, assets, level, lidx)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32) -> glam::Vec2 { glam::Vec2::new(x, y) }
+
+    #[test]
+    fn flip_sample() {
+        let p = v(0.25, 0.5);
+        assert_eq!(Flip::empty().sample(p), p);
+        assert_eq!(Flip::HORIZONTAL.sample(p), v(0.75, 0.5));
+        assert_eq!(Flip::VERTICAL.sample(p), v(0.25, 0.5));
+        assert_eq!(Flip::DIAGONAL.sample(p), v(0.5, 0.25));
+        assert_eq!((Flip::DIAGONAL | Flip::VERTICAL).sample(v(0.0, 0.25)), v(0.75, 0.0));
+    }
+
+    #[test]
+    fn flip_apply_undoes_sample() {
+        let p = v(0.25, 0.125);
+        for bits in 0..8u32 {
+            let flip = Flip::from_bits_truncate(bits << 29);
+            assert_eq!(flip.apply(flip.sample(p)), p, "{:?}", flip);
+        }
+    }
+
+    #[test]
+    fn flip_apply_rect() {
+        let r = collision::Rect { min: v(0.0, 0.0), max: v(0.25, 1.0) };
+        assert_eq!(Flip::HORIZONTAL.apply_rect(r), collision::Rect { min: v(0.75, 0.0), max: v(1.0, 1.0) });
+        assert_eq!(Flip::DIAGONAL.apply_rect(r), collision::Rect { min: v(0.0, 0.0), max: v(1.0, 0.25) });
+        assert_eq!(
+            (Flip::DIAGONAL | Flip::VERTICAL).apply_rect(r),
+            collision::Rect { min: v(0.0, 0.75), max: v(1.0, 1.0) },
+        );
+    }
+
+    const TILES: [u32; 4] = [1, 2, 3, 0x80000005];
+
+    fn encoded(compress: impl FnOnce(&[u8]) -> Vec<u8>) -> String {
+        let bytes: Vec<u8> = TILES.iter().flat_map(|t| t.to_le_bytes()).collect();
+        BASE64_STANDARD.encode(compress(&bytes))
+    }
+
+    #[test]
+    fn decode_csv() {
+        assert_eq!(decode_data("1,2,\n3, 2147483653\n", Some("csv"), None).unwrap(), TILES);
+    }
+
+    #[test]
+    fn decode_base64() {
+        let data = encoded(|b| b.to_vec());
+        assert_eq!(decode_data(&data, Some("base64"), None).unwrap(), TILES);
+        assert_eq!(decode_data(&format!("\n   {}\n", data), Some("base64"), Some("")).unwrap(), TILES);
+    }
+
+    #[test]
+    fn decode_zlib_and_gzip() {
+        use std::io::Write;
+        let zlib = encoded(|b| {
+            let mut e = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            e.write_all(b).unwrap();
+            e.finish().unwrap()
+        });
+        assert_eq!(decode_data(&zlib, Some("base64"), Some("zlib")).unwrap(), TILES);
+        let gzip = encoded(|b| {
+            let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            e.write_all(b).unwrap();
+            e.finish().unwrap()
+        });
+        assert_eq!(decode_data(&gzip, Some("base64"), Some("gzip")).unwrap(), TILES);
+    }
+
+    #[test]
+    fn decode_zstd() {
+        // TILES compressed with the zstd command line tool
+        let data = "KLUv/QRogQAAAQAAAAIAAAADAAAABQAAgMCcDu8=";
+        assert_eq!(decode_data(data, Some("base64"), Some("zstd")).unwrap(), TILES);
+    }
+
+    #[test]
+    fn decode_unknown() {
+        assert!(decode_data("AAAA", Some("base64"), Some("lzma")).is_err());
+        assert!(decode_data("1,2", Some("xml"), None).is_err());
+        assert!(decode_data("1,2", None, None).is_err());
+        assert!(decode_data("1,x", Some("csv"), None).is_err());
+    }
+
+    fn infinite_level() -> Level {
+        Level::new(r#"{
+            "orientation": "orthogonal", "infinite": true,
+            "width": 4, "height": 4, "tilewidth": 8, "tileheight": 8,
+            "tilesets": [],
+            "layers": [
+                {
+                    "name": "a", "id": 1, "type": "tilelayer", "x": 0, "y": 0,
+                    "opacity": 1, "visible": true,
+                    "chunks": [
+                        {"x": -16, "y": -16, "width": 16, "height": 16, "data": [7]},
+                        {"x": 32, "y": 0, "width": 2, "height": 1, "data": [0, 9]}
+                    ]
+                },
+                {
+                    "name": "b", "id": 2, "type": "tilelayer", "x": 0, "y": 0,
+                    "width": 2, "height": 2, "opacity": 1, "visible": true,
+                    "data": [0, 1, 0, 0]
+                },
+                {
+                    "name": "objects", "id": 3, "type": "objectgroup", "x": 0, "y": 0,
+                    "opacity": 1, "visible": true, "objects": []
+                }
+            ]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn infinite_layer_tiles_and_bounds() {
+        let level = infinite_level();
+        let a = level.layer("a").unwrap();
+        let tiles: Vec<_> = a.tiles().collect();
+        assert_eq!(tiles, vec![(glam::IVec2::new(-16, -16), 7), (glam::IVec2::new(33, 0), 9)]);
+        assert_eq!(a.tile(33, 0).unwrap(), 9);
+        assert_eq!(a.bounds(), (glam::IVec2::new(-16, -16), glam::IVec2::new(34, 1)));
+        assert_eq!(a.chunk_bounds(), Some((glam::IVec2::new(-1, -1), glam::IVec2::new(2, 0))));
+        let b = level.layer("b").unwrap();
+        assert_eq!(b.tiles().collect::<Vec<_>>(), vec![(glam::IVec2::new(1, 0), 1)]);
+        assert_eq!(level.tile_bounds(), (glam::IVec2::new(-16, -16), glam::IVec2::new(34, 2)));
+        assert_eq!(level.layer("objects").unwrap().chunk_bounds(), None);
+    }
+
+    #[test]
+    fn chunk_visibility() {
+        let bounds = (glam::IVec2::new(-1, -1), glam::IVec2::new(2, 0));
+        let half = glam::Vec2::new(10.0, 5.0);
+        // the view spans tiles -10 to 10 horizontally and -5 to 5 vertically
+        assert_eq!(
+            chunks_in_view(glam::Vec2::ZERO, half, bounds),
+            Some((glam::IVec2::new(-1, -1), glam::IVec2::new(0, 0))),
+        );
+        // clamped to the layer's chunks
+        assert_eq!(
+            chunks_in_view(glam::Vec2::new(40.0, 0.0), half, bounds),
+            Some((glam::IVec2::new(1, -1), glam::IVec2::new(2, 0))),
+        );
+        // an edge exactly on a chunk boundary still counts the chunk past it
+        assert_eq!(
+            chunks_in_view(glam::Vec2::new(6.0, 0.0), half, bounds).map(|(_, hi)| hi.x),
+            Some(1),
+        );
+        assert_eq!(chunks_in_view(glam::Vec2::new(0.0, 100.0), half, bounds), None);
+        assert_eq!(chunks_in_view(glam::Vec2::new(-100.0, 0.0), half, bounds), None);
+    }
+
+    #[test]
+    fn tmx_embedded_tileset() {
+        let level = Level::new(r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="1" tilewidth="8" tileheight="8" infinite="0">
+ <tileset firstgid="1" name="terrain" tilewidth="8" tileheight="8" tilecount="4" columns="2">
+  <image source="terrain.png" width="16" height="16"/>
+  <tile id="1">
+   <properties><property name="solid" type="bool" value="true"/></properties>
+  </tile>
+ </tileset>
+ <tileset firstgid="5" source="other.tsx"/>
+ <layer id="1" name="ground" width="2" height="1">
+  <data encoding="csv">2,5</data>
+ </layer>
+</map>"#).unwrap();
+        let (lid, source, _) = level.resolve_gid(2).unwrap();
+        assert_eq!((lid, source), (1, "terrain"));
+        let ts = level.embedded_tileset("terrain").unwrap();
+        assert_eq!((ts.imagewidth, ts.imageheight), (16, 16));
+        assert_eq!(ts.tile_property(1, "solid"), Some(&serde_json::Value::Bool(true)));
+        assert_eq!(level.resolve_gid(5).unwrap().1, "other.tsx");
+        assert!(level.embedded_tileset("other.tsx").is_none());
+    }
+}
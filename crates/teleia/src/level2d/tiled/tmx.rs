@@ -0,0 +1,250 @@
+// Tiled's XML formats (.tmx maps and .tsx tilesets), read into the same structures as the JSON formats
+
+use roxmltree::Node;
+
+use crate::{erm, Erm};
+use super::{
+    decode_data, Chunk, Err, Frame, Layer, LayerType, Level, LevelTileset, Object, ObjectGroup,
+    Orientation, Point, Property, Text, Tile, Tileset,
+};
+
+fn req<'a>(n: Node<'a, '_>, name: &str) -> Erm<&'a str> {
+    Ok(n.attribute(name).ok_or(Err::MissingAttribute(format!("{} on <{}>", name, n.tag_name().name())))?)
+}
+
+fn parse<T>(n: Node, name: &str) -> Erm<T>
+where T: std::str::FromStr, T::Err: std::error::Error + Send + Sync + 'static {
+    Ok(req(n, name)?.parse()?)
+}
+
+fn parse_or<T>(n: Node, name: &str, default: T) -> Erm<T>
+where T: std::str::FromStr, T::Err: std::error::Error + Send + Sync + 'static {
+    match n.attribute(name) {
+        Some(s) => Ok(s.parse()?),
+        None => Ok(default),
+    }
+}
+
+fn child<'a, 'input>(n: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    n.children().find(|c| c.has_tag_name(tag))
+}
+
+fn children<'a, 'input>(n: Node<'a, 'input>, tag: &'static str) -> impl Iterator<Item=Node<'a, 'input>> {
+    n.children().filter(move |c| c.has_tag_name(tag))
+}
+
+fn root<'a, 'input>(doc: &'a roxmltree::Document<'input>, tag: &str) -> Erm<Node<'a, 'input>> {
+    let n = doc.root_element();
+    if !n.has_tag_name(tag) {
+        return erm(Err::UnexpectedElement(format!("<{}>, expected <{}>", n.tag_name().name(), tag)));
+    }
+    Ok(n)
+}
+
+fn properties(n: Node) -> Erm<Vec<Property>> {
+    let Some(ps) = child(n, "properties") else { return Ok(Vec::new()) };
+    children(ps, "property").map(property).collect()
+}
+
+fn property(n: Node) -> Erm<Property> {
+    let ty = n.attribute("type").unwrap_or("string");
+    // multiline strings are stored as text instead of an attribute
+    let raw = n.attribute("value").or(n.text()).unwrap_or("");
+    let value = match ty {
+        "bool" => serde_json::Value::Bool(raw == "true"),
+        "int" | "object" => serde_json::Value::from(raw.parse::<i64>()?),
+        "float" => serde_json::Value::from(raw.parse::<f64>()?),
+        "class" => serde_json::Value::Object(
+            properties(n)?.into_iter().map(|p| (p.name, p.value)).collect()
+        ),
+        _ => serde_json::Value::String(raw.to_string()),
+    };
+    Ok(Property {
+        name: req(n, "name")?.to_string(),
+        ty: ty.to_string(),
+        value,
+    })
+}
+
+fn points(s: &str) -> Erm<Vec<Point>> {
+    s.split_whitespace().map(|p| {
+        let (x, y) = p.split_once(',').ok_or(Err::MissingAttribute(format!("y in point {}", p)))?;
+        Ok(Point { x: x.parse()?, y: y.parse()? })
+    }).collect()
+}
+
+fn object(n: Node) -> Erm<Object> {
+    let shape_points = |tag| -> Erm<Option<Vec<Point>>> {
+        match child(n, tag) {
+            Some(p) => Ok(Some(points(req(p, "points")?)?)),
+            None => Ok(None),
+        }
+    };
+    let text = match child(n, "text") {
+        Some(t) => Some(Text {
+            text: t.text().unwrap_or("").to_string(),
+            wrap: parse_or(t, "wrap", 0)? != 0,
+            color: t.attribute("color").map(str::to_string),
+            pixelsize: t.attribute("pixelsize").map(str::parse).transpose()?,
+        }),
+        None => None,
+    };
+    Ok(Object {
+        id: parse_or(n, "id", 0)?,
+        name: n.attribute("name").unwrap_or("").to_string(),
+        ty: n.attribute("type").or(n.attribute("class")).unwrap_or("").to_string(),
+        visible: parse_or(n, "visible", 1)? != 0,
+        properties: properties(n)?,
+        x: parse_or(n, "x", 0.0)?,
+        y: parse_or(n, "y", 0.0)?,
+        width: parse_or(n, "width", 0.0)?,
+        height: parse_or(n, "height", 0.0)?,
+        rotation: parse_or(n, "rotation", 0.0)?,
+        ellipse: child(n, "ellipse").is_some(),
+        point: child(n, "point").is_some(),
+        polygon: shape_points("polygon")?,
+        polyline: shape_points("polyline")?,
+        gid: n.attribute("gid").map(str::parse).transpose()?,
+        text,
+    })
+}
+
+/// Tiles in a <data> or <chunk> element, using the encoding given on <data>
+fn layer_data(n: Node, data: Node) -> Erm<Vec<u32>> {
+    match data.attribute("encoding") {
+        // the deprecated XML encoding, one element per tile
+        None => children(n, "tile").map(|t| parse_or(t, "gid", 0)).collect(),
+        Some(enc) => decode_data(n.text().unwrap_or(""), Some(enc), data.attribute("compression")),
+    }
+}
+
+fn chunk(n: Node, data: Node) -> Erm<Chunk> {
+    Ok(Chunk {
+        x: parse(n, "x")?,
+        y: parse(n, "y")?,
+        width: parse(n, "width")?,
+        height: parse(n, "height")?,
+        data: layer_data(n, data)?,
+        raw_data: None,
+    })
+}
+
+fn layer(n: Node) -> Erm<Option<Layer>> {
+    let ty = match n.tag_name().name() {
+        "layer" => LayerType::Tile,
+        "objectgroup" => LayerType::ObjectGroup,
+        "imagelayer" => LayerType::Image,
+        "group" => LayerType::Group,
+        _ => return Ok(None),
+    };
+    let (data, chunks) = match child(n, "data") {
+        Some(d) if child(d, "chunk").is_some() => (
+            Vec::new(),
+            children(d, "chunk").map(|c| chunk(c, d)).collect::<Erm<_>>()?,
+        ),
+        Some(d) => (layer_data(d, d)?, Vec::new()),
+        None => (Vec::new(), Vec::new()),
+    };
+    Ok(Some(Layer {
+        name: n.attribute("name").unwrap_or("").to_string(),
+        id: parse_or(n, "id", 0)?,
+        ty,
+        width: parse_or(n, "width", 0)?,
+        height: parse_or(n, "height", 0)?,
+        x: parse_or(n, "x", 0)?,
+        y: parse_or(n, "y", 0)?,
+        offsetx: parse_or(n, "offsetx", 0.0)?,
+        offsety: parse_or(n, "offsety", 0.0)?,
+        parallaxx: parse_or(n, "parallaxx", 1.0)?,
+        parallaxy: parse_or(n, "parallaxy", 1.0)?,
+        opacity: parse_or(n, "opacity", 1.0)?,
+        visible: parse_or(n, "visible", 1)? != 0,
+        data,
+        raw_data: None,
+        encoding: None,
+        compression: None,
+        chunks,
+        objects: children(n, "object").map(object).collect::<Erm<_>>()?,
+        properties: properties(n)?,
+    }))
+}
+
+fn orientation(s: &str) -> Orientation {
+    match s {
+        "isometric" => Orientation::Isometric,
+        "staggered" => Orientation::Staggered,
+        "hexagonal" => Orientation::Hexagonal,
+        _ => Orientation::Orthogonal,
+    }
+}
+
+pub fn level(src: &str) -> Erm<Level> {
+    let doc = roxmltree::Document::parse(src)?;
+    let map = root(&doc, "map")?;
+    let mut layers = Vec::new();
+    for n in map.children() {
+        if let Some(l) = layer(n)? { layers.push(l); }
+    }
+    let tilesets = children(map, "tileset").map(|t| {
+        let firstgid = parse(t, "firstgid")?;
+        Ok(match t.attribute("source") {
+            Some(s) => LevelTileset { firstgid, source: s.to_string(), embedded: None },
+            // embedded tilesets have no file, so they are looked up by name instead
+            None => {
+                let ts = tileset_element(t)?;
+                LevelTileset { firstgid, source: ts.name.clone(), embedded: Some(ts) }
+            },
+        })
+    }).collect::<Erm<_>>()?;
+    Ok(Level {
+        orientation: orientation(map.attribute("orientation").unwrap_or("")),
+        infinite: parse_or(map, "infinite", 0)? != 0,
+        width: parse(map, "width")?,
+        height: parse(map, "height")?,
+        tilewidth: parse(map, "tilewidth")?,
+        tileheight: parse(map, "tileheight")?,
+        layers,
+        tilesets,
+        properties: properties(map)?,
+    })
+}
+
+fn tile(n: Node) -> Erm<Tile> {
+    let objectgroup = match child(n, "objectgroup") {
+        Some(og) => Some(ObjectGroup { objects: children(og, "object").map(object).collect::<Erm<_>>()? }),
+        None => None,
+    };
+    let animation = match child(n, "animation") {
+        Some(a) => children(a, "frame").map(|f| Ok(Frame {
+            tileid: parse(f, "tileid")?,
+            duration: parse(f, "duration")?,
+        })).collect::<Erm<_>>()?,
+        None => Vec::new(),
+    };
+    Ok(Tile {
+        id: parse(n, "id")?,
+        properties: properties(n)?,
+        objectgroup,
+        animation,
+    })
+}
+
+pub fn tileset(src: &str) -> Erm<Tileset> {
+    let doc = roxmltree::Document::parse(src)?;
+    tileset_element(root(&doc, "tileset")?)
+}
+
+/// A <tileset>, either the root of a .tsx file or embedded in a map
+fn tileset_element(ts: Node) -> Erm<Tileset> {
+    let image = child(ts, "image").ok_or(Err::MissingAttribute("<image> in <tileset>".to_string()))?;
+    Ok(Tileset {
+        name: ts.attribute("name").unwrap_or("").to_string(),
+        imagewidth: parse(image, "width")?,
+        imageheight: parse(image, "height")?,
+        tilewidth: parse(ts, "tilewidth")?,
+        tileheight: parse(ts, "tileheight")?,
+        margin: parse_or(ts, "margin", 0)?,
+        spacing: parse_or(ts, "spacing", 0)?,
+        tiles: children(ts, "tile").map(tile).collect::<Erm<_>>()?,
+    })
+}
@@ -19,6 +19,10 @@ pub mod net;
 pub mod physics;
 pub mod save;
 pub mod level2d;
+pub mod gamepad;
+pub mod replay;
+pub mod capture;
+pub mod picking;
 
 pub use utils::{erm, install_error_handler, Erm};
 pub use state::Tick;
@@ -47,9 +51,17 @@ bitflags! {
         const NORESIZE         = 0b00000100;
         const EGL              = 0b00001000;
         const FULLSCREEN_MOUSE = 0b00010000;
+        const HEADLESS         = 0b00100000;
+        const GIF_BUFFER       = 0b01000000;
+        /// Enable the capture hotkeys (F2 screenshot, F3 video, F4 GIF) even when debug mode is off
+        const CAPTURE_KEYS     = 0b10000000;
     }
 }
 
+/// Browsers report smooth scrolling in pixels, convert to roughly one wheel notch
+#[cfg(target_arch = "wasm32")]
+const SCROLL_PIXELS_PER_LINE: f32 = 100.0;
+
 static mut CTX: Option<*const context::Context> = None;
 static mut ST: Option<*mut state::State> = None;
 static mut G: Option<*mut std::ffi::c_void> = None;
@@ -133,6 +145,8 @@ where
         window.set_size_polling(true);
         window.set_focus_polling(true);
         window.set_cursor_pos_polling(true);
+        window.set_scroll_polling(true);
+        window.set_char_polling(true);
         let gl = unsafe {
             glow::Context::from_loader_function(|s| window.get_proc_address(s) as *const _)
         };
@@ -155,11 +169,15 @@ where
         G = Some(game as *mut G as *mut std::ffi::c_void);
     }
 
+    st.initialize_keybindings(ctx, game);
     game.initialize(ctx, st)?;
     st.initialize_audio(ctx, game)?;
+    st.last_frame = state::now(ctx); // don't try to catch up on time spent loading
+    let mut locked_cursor: Option<(f64, f64)> = None;
     'running: loop {
         if ctx.window.borrow().should_close() {
             game.finalize(ctx, st)?;
+            st.stop_video()?;
             log::info!("bye!");
             break 'running;
         }
@@ -171,17 +189,36 @@ where
                     st.keys = state::Keys::new();
                 },
                 glfw::WindowEvent::CursorPos(x, y) => {
-                    st.mouse_moved(ctx, x as f32, y as f32, game)?;
+                    if ctx.is_mouse_locked() {
+                        // the cursor position is virtual and unbounded while disabled, so only the difference matters
+                        if let Some((lx, ly)) = locked_cursor {
+                            st.mouse_motion(ctx, game, (x - lx) as f32, (y - ly) as f32)?;
+                        }
+                        locked_cursor = Some((x, y));
+                    } else {
+                        locked_cursor = None;
+                        st.mouse_moved(ctx, x as f32, y as f32, game)?;
+                    }
                 }
-                glfw::WindowEvent::MouseButton(_, glfw::Action::Press, _) => {
-                    st.mouse_pressed(ctx, game)?;
+                glfw::WindowEvent::Scroll(dx, dy) => {
+                    st.mouse_scrolled(ctx, game, dx as f32, dy as f32)?;
                 },
-                glfw::WindowEvent::MouseButton(_, glfw::Action::Release, _) => {
-                    st.mouse_released(ctx, game)?;
+                glfw::WindowEvent::MouseButton(b, glfw::Action::Press, _) => {
+                    st.mouse_pressed(ctx, game, state::MouseButton::new(b))?;
+                },
+                glfw::WindowEvent::MouseButton(b, glfw::Action::Release, _) => {
+                    st.mouse_released(ctx, game, state::MouseButton::new(b))?;
                 },
                 glfw::WindowEvent::Key(key, _, glfw::Action::Press, _) => {
+                    st.text_key(ctx, game, state::Keycode::new(key))?;
                     st.key_pressed(ctx, game, state::Keycode::new(key))?;
                 },
+                glfw::WindowEvent::Key(key, _, glfw::Action::Repeat, _) => {
+                    st.text_key(ctx, game, state::Keycode::new(key))?;
+                },
+                glfw::WindowEvent::Char(c) => {
+                    st.text_input(ctx, game, c)?;
+                },
                 glfw::WindowEvent::Key(key, _, glfw::Action::Release, _) => {
                     st.key_released(ctx, game, state::Keycode::new(key))?;
                 },
@@ -191,6 +228,7 @@ where
         if ctx.resize_necessary() {
             st.handle_resize(ctx);
         }
+        st.poll_gamepad(ctx, game)?;
         // if let Some(f) = &mut st.request {
         //     match std::future::Future::poll(f.as_mut(), &mut st.waker_ctx) {
         //         std::task::Poll::Pending => {},
@@ -210,6 +248,77 @@ where
     Ok(())
 }
 
+/// Drive a game without a display, for tests and CI.
+/// An offscreen OSMesa context is created on GLFW's null platform, so no GPU or window system is needed.
+/// Each tick, `input` is called before the update (e.g. to press keys via `State::key_pressed`).
+/// If `render` is false, `Game::render` is never called.
+/// Returns the game and state after `ticks` updates, so `State::tick` ends at `state::INITIAL_TICK + ticks`.
+/// Fails (rather than panicking) if GLFW's null platform or OSMesa isn't available.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless<F, G, I>(
+    w: u32, h: u32, options: Options,
+    ticks: Tick, render: bool,
+    gnew: F, mut input: I,
+) -> Erm<(G, state::State)>
+where
+    G: state::Game + 'static,
+    F: for<'c> FnOnce(&'c context::Context, &mut state::State) -> G,
+    I: FnMut(&context::Context, &mut state::State, &mut G) -> Erm<()>,
+{
+    let (rglfw, rwindow, gl) = {
+        use glfw::log_errors;
+        glfw::init_hint(glfw::InitHint::Platform(glfw::Platform::Null));
+        let mut glfw = glfw::init(glfw::log_errors!())
+            .wrap_err("failed to initialize GLFW on the null platform")?;
+        glfw.window_hint(glfw::WindowHint::Visible(false));
+        glfw.window_hint(glfw::WindowHint::ContextCreationApi(glfw::ContextCreationApi::OsMesa));
+        let Some((mut window, _events)) = glfw.create_window(w as _, h as _, "teleia", glfw::WindowMode::Windowed) else {
+            return utils::erm_msg("failed to create offscreen context (is OSMesa available?)");
+        };
+        window.make_current();
+        let gl = unsafe {
+            glow::Context::from_loader_function(|s| window.get_proc_address(s) as *const _)
+        };
+        (glfw, window, gl)
+    };
+    let glfw = std::cell::RefCell::new(rglfw);
+    let window = std::cell::RefCell::new(rwindow);
+
+    let ctx = Box::new(context::Context::new(
+        glfw, window, gl,
+        w as f32, h as f32, options | Options::HEADLESS,
+    ));
+    let mut st = Box::new(state::State::new(&ctx));
+    let mut game = Box::new(gnew(&ctx, &mut st));
+
+    unsafe {
+        CTX = Some(&*ctx as _);
+        ST = Some(&mut *st as _);
+        G = Some(&mut *game as *mut G as *mut std::ffi::c_void);
+    }
+
+    let res: Erm<()> = try {
+        game.initialize(&ctx, &mut st)?;
+        for _ in 0..ticks {
+            input(&ctx, &mut st, &mut game)?;
+            st.run_tick(&ctx, &mut *game)?;
+            if render {
+                st.run_render(&ctx, &mut *game)?;
+            }
+        }
+        game.finalize(&ctx, &mut st)?;
+        st.stop_video()?;
+    };
+
+    unsafe {
+        CTX = None;
+        ST = None;
+        G = None;
+    }
+    res?;
+    Ok((*game, *st))
+}
+
 #[cfg(target_arch = "wasm32")]
 pub fn run<'a, F, G>(w: u32, h: u32, options: Options, gnew: F)
 where
@@ -260,8 +369,10 @@ where
         G = Some(game as *mut G as *mut std::ffi::c_void);
     }
 
+    st.initialize_keybindings(ctx, game);
     let _ = game.initialize(ctx, st);
     let _ = st.initialize_audio(ctx, game);
+    st.last_frame = state::now(ctx); // don't try to catch up on time spent loading
     let res = std::rc::Rc::new(std::cell::RefCell::new(Ok(())));
     let result = res.clone();
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
@@ -284,30 +395,53 @@ where
                         st.keys = state::Keys::new();
                     },
                     winit::event::WindowEvent::CursorMoved { position, ..} => {
-                        st.mouse_moved(&ctx, position.x as f32, position.y as f32, game)?;
+                        if !ctx.is_mouse_locked() {
+                            st.mouse_moved(&ctx, position.x as f32, position.y as f32, game)?;
+                        }
+                    },
+                    winit::event::WindowEvent::MouseWheel { delta, .. } => match delta {
+                        winit::event::MouseScrollDelta::LineDelta(dx, dy) => {
+                            st.mouse_scrolled(&ctx, game, *dx, *dy)?;
+                        },
+                        winit::event::MouseScrollDelta::PixelDelta(p) => {
+                            st.mouse_scrolled(&ctx, game,
+                                p.x as f32 / SCROLL_PIXELS_PER_LINE,
+                                p.y as f32 / SCROLL_PIXELS_PER_LINE,
+                            )?;
+                        },
                     },
                     winit::event::WindowEvent::MouseInput {
                         state,
+                        button,
                         ..
                     } => match state {
                         winit::event::ElementState::Pressed => {
-                            st.mouse_pressed(&ctx, game)?;
+                            st.mouse_pressed(&ctx, game, state::MouseButton::new(*button))?;
                         },
                         winit::event::ElementState::Released => {
-                            st.mouse_released(&ctx, game)?;
+                            st.mouse_released(&ctx, game, state::MouseButton::new(*button))?;
                         },
                     }
                     winit::event::WindowEvent::KeyboardInput {
                         event: winit::event::KeyEvent {
                             physical_key: winit::keyboard::PhysicalKey::Code(key),
                             state,
-                            repeat: false,
+                            repeat,
+                            text,
                             ..
                         },
                         ..
                     } => match state {
                         winit::event::ElementState::Pressed => {
-                            st.key_pressed(&ctx, game, state::Keycode { kc: *key })?;
+                            st.text_key(&ctx, game, state::Keycode { kc: *key })?;
+                            if let Some(text) = text {
+                                for c in text.chars() {
+                                    st.text_input(&ctx, game, c)?;
+                                }
+                            }
+                            if !repeat {
+                                st.key_pressed(&ctx, game, state::Keycode { kc: *key })?;
+                            }
                         },
                         winit::event::ElementState::Released => {
                             st.key_released(&ctx, game, state::Keycode { kc: *key })?;
@@ -316,12 +450,20 @@ where
                     _ => {},
                 },
                 
+                winit::event::Event::DeviceEvent {
+                    event: winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) },
+                    ..
+                } => {
+                    st.mouse_motion(&ctx, game, *dx as f32, *dy as f32)?;
+                },
+
                 winit::event::Event::AboutToWait => {
                     if ctx.resize_necessary() {
                         #[cfg(target_arch = "wasm32")]
                         ctx.maximize_canvas();
                         st.handle_resize(&ctx);
                     }
+                    st.poll_gamepad(&ctx, game)?;
                     // if let Some(f) = &mut st.request {
                     //     match std::future::Future::poll(f.as_mut(), &mut st.waker_ctx) {
                     //         std::task::Poll::Pending => {},
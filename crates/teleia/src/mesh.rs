@@ -11,6 +11,13 @@ pub const ATTRIB_COLOR: u32 = 5;
 
 type Buffer = <glow::Context as glow::HasContext>::Buffer;
 
+/// Copy of a mesh's geometry kept in main memory (e.g. for building colliders)
+#[derive(Debug, Clone, Default)]
+pub struct CpuData {
+    pub positions: Vec<glam::Vec3>,
+    pub indices: Vec<u32>,
+}
+
 pub struct Mesh {
     pub vao: glow::VertexArray,
     pub vbo_vertex: Buffer,
@@ -21,6 +28,8 @@ pub struct Mesh {
     pub index_count: usize,
     pub index_type: u32, // glow::BYTE, glow::FLOAT, etc.
     pub index_offset: i32,
+    /// If present, uploads also keep their positions and indices here
+    pub cpu: Option<CpuData>,
 }
 
 impl Mesh {
@@ -63,9 +72,15 @@ impl Mesh {
                 index_count: 0,
                 index_type: glow::UNSIGNED_INT,
                 index_offset: 0,
+                cpu: None,
             }
         }
     }
+
+    /// Keep a CPU-side copy of positions and indices on subsequent uploads
+    pub fn retain_cpu_data(&mut self) {
+        if self.cpu.is_none() { self.cpu = Some(CpuData::default()); }
+    }
     pub fn upload(
         &mut self,
         ctx: &context::Context,
@@ -74,6 +89,10 @@ impl Mesh {
         snormals: Option<&[f32]>,
         stexcoords: Option<&[f32]>,
     ) {
+        if let Some(cpu) = &mut self.cpu {
+            cpu.positions = vertices.chunks_exact(3).map(glam::Vec3::from_slice).collect();
+            cpu.indices = indices.to_vec();
+        }
         unsafe {
             ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo_vertex));
             ctx.gl.buffer_data_u8_slice(
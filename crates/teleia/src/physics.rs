@@ -0,0 +1,467 @@
+use rapier3d::prelude as rp;
+
+use crate::{mesh, picking, renderer, scene, state};
+
+pub const DEFAULT_GRAVITY: glam::Vec3 = glam::Vec3::new(0.0, -9.81, 0.0);
+
+/// Rapier's debug colors are HSLA
+fn hsla_to_rgba(c: rp::DebugColor) -> glam::Vec4 {
+    let [h, s, l, a] = c;
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let hp = (h / 60.0).rem_euclid(6.0);
+    let x = chroma * (1.0 - (hp % 2.0 - 1.0).abs());
+    let (r, g, b) = match hp as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - chroma / 2.0;
+    glam::Vec4::new(r + m, g + m, b + m, a)
+}
+
+impl rp::DebugRenderBackend for renderer::DebugDraw {
+    fn draw_line(&mut self, _object: rp::DebugRenderObject, a: rp::Point<rp::Real>, b: rp::Point<rp::Real>, color: rp::DebugColor) {
+        self.line(a.into(), b.into(), hsla_to_rgba(color));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Body(pub rp::RigidBodyHandle);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Collider(pub rp::ColliderHandle);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Two colliders started touching (or a collider entered a sensor)
+    Started { a: Collider, b: Collider, sensor: bool },
+    /// Two colliders stopped touching (or a collider left a sensor)
+    Stopped { a: Collider, b: Collider, sensor: bool },
+    /// Contact force above a collider's threshold (see ColliderBuilder::contact_force_event_threshold)
+    Force { a: Collider, b: Collider, magnitude: f32 },
+}
+
+/// Event handler that just stashes events until the game asks for them
+#[derive(Default)]
+struct Collector {
+    events: std::sync::Mutex<Vec<Event>>,
+}
+impl rp::EventHandler for Collector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &rp::RigidBodySet, _colliders: &rp::ColliderSet,
+        event: rp::CollisionEvent,
+        _contact_pair: Option<&rp::ContactPair>,
+    ) {
+        let ev = match event {
+            rp::CollisionEvent::Started(a, b, flags) => Event::Started {
+                a: Collider(a), b: Collider(b),
+                sensor: flags.contains(rp::CollisionEventFlags::SENSOR),
+            },
+            rp::CollisionEvent::Stopped(a, b, flags) => Event::Stopped {
+                a: Collider(a), b: Collider(b),
+                sensor: flags.contains(rp::CollisionEventFlags::SENSOR),
+            },
+        };
+        if let Ok(mut evs) = self.events.lock() { evs.push(ev); }
+    }
+    fn handle_contact_force_event(
+        &self,
+        _dt: rp::Real,
+        _bodies: &rp::RigidBodySet, _colliders: &rp::ColliderSet,
+        contact_pair: &rp::ContactPair,
+        total_force_magnitude: rp::Real,
+    ) {
+        let ev = Event::Force {
+            a: Collider(contact_pair.collider1), b: Collider(contact_pair.collider2),
+            magnitude: total_force_magnitude,
+        };
+        if let Ok(mut evs) = self.events.lock() { evs.push(ev); }
+    }
+}
+
+pub fn isometry(pos: glam::Vec3, rot: glam::Quat) -> rp::Isometry<rp::Real> {
+    rp::Isometry::from_parts(
+        nalgebra::Translation3::from(nalgebra::Vector3::from(pos)),
+        nalgebra::UnitQuaternion::from(rot),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshShape {
+    /// Exact triangles, for static level geometry
+    TriMesh,
+    /// Convex hull of the vertices, for dynamic bodies
+    ConvexHull,
+}
+
+/// Build a collision shape from a mesh's retained CPU data, with the transform baked into the vertices.
+/// Returns None if the mesh didn't retain its data, isn't made of triangles, or is degenerate.
+pub fn mesh_shape(m: &mesh::Mesh, transform: glam::Mat4, kind: MeshShape) -> Option<rp::SharedShape> {
+    let cpu = m.cpu.as_ref()?;
+    let points: Vec<rp::Point<rp::Real>> = cpu.positions.iter()
+        .map(|p| nalgebra::Point3::from(nalgebra::Vector3::from(transform.transform_point3(*p))))
+        .collect();
+    match kind {
+        MeshShape::TriMesh => {
+            if m.mode != glow::TRIANGLES { return None; }
+            let tris: Vec<[u32; 3]> = cpu.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+            rp::SharedShape::trimesh(points, tris).ok()
+        },
+        MeshShape::ConvexHull => rp::SharedShape::convex_hull(&points),
+    }
+}
+
+/// A rapier physics world, with handles and accessors that use glam types.
+/// Call step once per tick from Game::update; it is a no-op if the world has already stepped this tick.
+pub struct World {
+    pub gravity: glam::Vec3,
+    pub integration_parameters: rp::IntegrationParameters,
+    pub pipeline: rp::PhysicsPipeline,
+    pub islands: rp::IslandManager,
+    pub broad_phase: rp::DefaultBroadPhase,
+    pub narrow_phase: rp::NarrowPhase,
+    pub bodies: rp::RigidBodySet,
+    pub colliders: rp::ColliderSet,
+    pub impulse_joints: rp::ImpulseJointSet,
+    pub multibody_joints: rp::MultibodyJointSet,
+    pub ccd_solver: rp::CCDSolver,
+    pub query_pipeline: rp::QueryPipeline,
+    pub debug_pipeline: rp::DebugRenderPipeline,
+    collector: Collector,
+    last_tick: Option<state::Tick>,
+}
+
+impl Default for World {
+    fn default() -> Self { Self::new() }
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            gravity: DEFAULT_GRAVITY,
+            integration_parameters: rp::IntegrationParameters::default(),
+            pipeline: rp::PhysicsPipeline::new(),
+            islands: rp::IslandManager::new(),
+            broad_phase: rp::DefaultBroadPhase::new(),
+            narrow_phase: rp::NarrowPhase::new(),
+            bodies: rp::RigidBodySet::new(),
+            colliders: rp::ColliderSet::new(),
+            impulse_joints: rp::ImpulseJointSet::new(),
+            multibody_joints: rp::MultibodyJointSet::new(),
+            ccd_solver: rp::CCDSolver::new(),
+            query_pipeline: rp::QueryPipeline::new(),
+            debug_pipeline: rp::DebugRenderPipeline::new(
+                rp::DebugRenderStyle::default(),
+                rp::DebugRenderMode::COLLIDER_SHAPES | rp::DebugRenderMode::CONTACTS,
+            ),
+            collector: Collector::default(),
+            last_tick: None,
+        }
+    }
+
+    /// Advance the simulation by one tick
+    pub fn step(&mut self, st: &state::State) {
+        if self.last_tick == Some(st.tick) { return; }
+        self.last_tick = Some(st.tick);
+        self.integration_parameters.dt = st.delta_time() as _;
+        self.pipeline.step(
+            &self.gravity.into(),
+            &self.integration_parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            Some(&mut self.query_pipeline),
+            &(),
+            &self.collector,
+        );
+    }
+
+    /// Take all collision and contact force events since the last call
+    pub fn events(&mut self) -> Vec<Event> {
+        self.collector.events.get_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn add_body(&mut self, body: rp::RigidBodyBuilder) -> Body {
+        Body(self.bodies.insert(body))
+    }
+    pub fn add_dynamic(&mut self, pos: glam::Vec3) -> Body {
+        self.add_body(rp::RigidBodyBuilder::dynamic().translation(pos.into()))
+    }
+    pub fn add_fixed(&mut self, pos: glam::Vec3) -> Body {
+        self.add_body(rp::RigidBodyBuilder::fixed().translation(pos.into()))
+    }
+    pub fn add_kinematic(&mut self, pos: glam::Vec3) -> Body {
+        self.add_body(rp::RigidBodyBuilder::kinematic_position_based().translation(pos.into()))
+    }
+
+    /// Add a collider, optionally attached to a body. Collision events are always enabled.
+    pub fn add_collider(&mut self, collider: rp::ColliderBuilder, parent: Option<Body>) -> Collider {
+        let mut collider = collider;
+        collider.active_events |= rp::ActiveEvents::COLLISION_EVENTS;
+        Collider(match parent {
+            Some(Body(b)) => self.colliders.insert_with_parent(collider, b, &mut self.bodies),
+            None => self.colliders.insert(collider),
+        })
+    }
+    pub fn add_cuboid(&mut self, half_extents: glam::Vec3, parent: Option<Body>) -> Collider {
+        self.add_collider(rp::ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z), parent)
+    }
+    pub fn add_ball(&mut self, radius: f32, parent: Option<Body>) -> Collider {
+        self.add_collider(rp::ColliderBuilder::ball(radius), parent)
+    }
+    /// Capsule along the Y axis
+    pub fn add_capsule(&mut self, half_height: f32, radius: f32, parent: Option<Body>) -> Collider {
+        self.add_collider(rp::ColliderBuilder::capsule_y(half_height, radius), parent)
+    }
+
+    /// Add a collider shaped like a mesh (see mesh_shape)
+    pub fn add_mesh_collider(&mut self, m: &mesh::Mesh, transform: glam::Mat4, kind: MeshShape, parent: Option<Body>) -> Option<Collider> {
+        let shape = mesh_shape(m, transform, kind)?;
+        Some(self.add_collider(rp::ColliderBuilder::new(shape), parent))
+    }
+
+    /// Add a collider for every primitive in a scene, placed using the scene's node transforms.
+    /// The scene must be loaded with Scene::from_gltf_retained.
+    pub fn add_scene_colliders(&mut self, s: &scene::Scene, transform: glam::Mat4, kind: MeshShape, parent: Option<Body>) -> Vec<Collider> {
+        let mut ret = Vec::new();
+        for (ni, t) in s.node_transforms() {
+            let Some(o) = s.nodes[ni].object.and_then(|i| s.objects.get(i)) else { continue };
+            for p in &o.primitives {
+                if let Some(c) = self.add_mesh_collider(&p.mesh, transform.mul_mat4(&t), kind, parent) {
+                    ret.push(c);
+                } else {
+                    log::warn!("skipping primitive of node {} with no usable geometry", ni);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Queue collider outlines and contacts on the debug drawer
+    pub fn debug_draw(&mut self, dd: &mut renderer::DebugDraw) {
+        self.debug_pipeline.render(
+            dd,
+            &self.bodies,
+            &self.colliders,
+            &self.impulse_joints,
+            &self.multibody_joints,
+            &self.narrow_phase,
+        );
+    }
+
+    /// Find the first collider hit by a ray within max_distance, optionally ignoring one body's colliders
+    pub fn cast_ray(&self, ray: &picking::Ray, max_distance: f32, exclude: Option<Body>) -> Option<picking::Hit<Collider>> {
+        let r = rp::Ray::new(
+            nalgebra::Point3::from(nalgebra::Vector3::from(ray.origin)),
+            ray.dir.into(),
+        );
+        let mut filter = rp::QueryFilter::default();
+        if let Some(Body(b)) = exclude { filter = filter.exclude_rigid_body(b); }
+        let (c, hit) = self.query_pipeline.cast_ray_and_get_normal(
+            &self.bodies, &self.colliders,
+            &r, max_distance, true,
+            filter,
+        )?;
+        Some(picking::Hit {
+            target: Collider(c),
+            distance: hit.time_of_impact,
+            point: ray.at(hit.time_of_impact),
+            normal: hit.normal.into(),
+        })
+    }
+
+    pub fn remove_body(&mut self, Body(b): Body) {
+        self.bodies.remove(
+            b,
+            &mut self.islands, &mut self.colliders,
+            &mut self.impulse_joints, &mut self.multibody_joints,
+            true,
+        );
+    }
+    pub fn remove_collider(&mut self, Collider(c): Collider) {
+        self.colliders.remove(c, &mut self.islands, &mut self.bodies, true);
+    }
+
+    pub fn body(&self, Body(b): Body) -> Option<&rp::RigidBody> { self.bodies.get(b) }
+    pub fn body_mut(&mut self, Body(b): Body) -> Option<&mut rp::RigidBody> { self.bodies.get_mut(b) }
+    pub fn collider(&self, Collider(c): Collider) -> Option<&rp::Collider> { self.colliders.get(c) }
+    pub fn collider_mut(&mut self, Collider(c): Collider) -> Option<&mut rp::Collider> { self.colliders.get_mut(c) }
+
+    /// The body a collider is attached to
+    pub fn parent(&self, c: Collider) -> Option<Body> {
+        self.collider(c)?.parent().map(Body)
+    }
+    /// Arbitrary data attached to a collider (e.g. an entity id), for identifying colliders in events
+    pub fn user_data(&self, c: Collider) -> u128 {
+        self.collider(c).map(|c| c.user_data).unwrap_or(0)
+    }
+    pub fn set_user_data(&mut self, c: Collider, data: u128) {
+        if let Some(c) = self.collider_mut(c) { c.user_data = data; }
+    }
+
+    pub fn position(&self, b: Body) -> glam::Vec3 {
+        self.body(b).map(|b| (*b.translation()).into()).unwrap_or_default()
+    }
+    pub fn rotation(&self, b: Body) -> glam::Quat {
+        self.body(b).map(|b| (*b.rotation()).into()).unwrap_or_default()
+    }
+    /// Model matrix for rendering a body
+    pub fn transform(&self, b: Body) -> glam::Mat4 {
+        glam::Mat4::from_rotation_translation(self.rotation(b), self.position(b))
+    }
+    pub fn velocity(&self, b: Body) -> glam::Vec3 {
+        self.body(b).map(|b| (*b.linvel()).into()).unwrap_or_default()
+    }
+
+    pub fn set_position(&mut self, b: Body, pos: glam::Vec3) {
+        if let Some(b) = self.body_mut(b) {
+            if b.is_kinematic() {
+                b.set_next_kinematic_translation(pos.into());
+            } else {
+                b.set_translation(pos.into(), true);
+            }
+        }
+    }
+    pub fn set_rotation(&mut self, b: Body, rot: glam::Quat) {
+        if let Some(b) = self.body_mut(b) {
+            if b.is_kinematic() {
+                b.set_next_kinematic_rotation(rot.into());
+            } else {
+                b.set_rotation(rot.into(), true);
+            }
+        }
+    }
+    pub fn set_velocity(&mut self, b: Body, vel: glam::Vec3) {
+        if let Some(b) = self.body_mut(b) { b.set_linvel(vel.into(), true); }
+    }
+    pub fn apply_impulse(&mut self, b: Body, impulse: glam::Vec3) {
+        if let Some(b) = self.body_mut(b) { b.apply_impulse(impulse.into(), true); }
+    }
+}
+
+/// A kinematic capsule that walks: it climbs steps and gentle slopes, slides along walls, and snaps to the ground.
+pub struct CharacterController {
+    pub body: Body,
+    pub collider: Collider,
+    pub controller: rp::KinematicCharacterController,
+    /// Horizontal speed in units per second
+    pub speed: f32,
+    pub jump_speed: f32,
+    /// Used when pushing dynamic bodies out of the way
+    pub mass: f32,
+    /// Vertical velocity from gravity and jumping
+    pub vertical_velocity: f32,
+    pub grounded: bool,
+    pub sliding: bool,
+}
+
+impl CharacterController {
+    /// Create a kinematic body with a Y-aligned capsule at the given position (the capsule's center)
+    pub fn new(world: &mut World, pos: glam::Vec3, half_height: f32, radius: f32) -> Self {
+        let body = world.add_kinematic(pos);
+        let collider = world.add_capsule(half_height, radius, Some(body));
+        let controller = rp::KinematicCharacterController {
+            autostep: Some(rp::CharacterAutostep {
+                max_height: rp::CharacterLength::Absolute(half_height * 0.5),
+                min_width: rp::CharacterLength::Absolute(radius * 0.5),
+                include_dynamic_bodies: false,
+            }),
+            max_slope_climb_angle: 45.0f32.to_radians(),
+            min_slope_slide_angle: 30.0f32.to_radians(),
+            snap_to_ground: Some(rp::CharacterLength::Absolute(half_height * 0.25)),
+            ..Default::default()
+        };
+        Self {
+            body, collider,
+            controller,
+            speed: 4.0,
+            jump_speed: 5.0,
+            mass: 70.0,
+            vertical_velocity: 0.0,
+            grounded: false,
+            sliding: false,
+        }
+    }
+
+    pub fn position(&self, world: &World) -> glam::Vec3 { world.position(self.body) }
+
+    /// Horizontal direction from the directional keys, relative to a facing direction (normalized, or zero)
+    pub fn input_direction(keys: &state::Keys, forward: glam::Vec3) -> glam::Vec3 {
+        let forward = glam::Vec3::new(forward.x, 0.0, forward.z).normalize_or_zero();
+        let right = glam::Vec3::Y.cross(forward);
+        let mut dir = glam::Vec3::ZERO;
+        if keys.up() { dir += forward; }
+        if keys.down() { dir -= forward; }
+        if keys.right() { dir += right; }
+        if keys.left() { dir -= right; }
+        dir.normalize_or_zero()
+    }
+
+    /// Start a jump, if standing on the ground
+    pub fn jump(&mut self) -> bool {
+        if self.grounded {
+            self.vertical_velocity = self.jump_speed;
+            self.grounded = false;
+            true
+        } else { false }
+    }
+
+    /// Move according to the directional keys for one tick. Call before World::step.
+    pub fn update(&mut self, world: &mut World, st: &state::State, forward: glam::Vec3) {
+        let dir = Self::input_direction(&st.keys, forward);
+        self.move_by(world, st, dir * self.speed);
+    }
+
+    /// Move with the given horizontal velocity for one tick, applying gravity. Call before World::step.
+    pub fn move_by(&mut self, world: &mut World, st: &state::State, horizontal: glam::Vec3) {
+        let dt = st.delta_time() as f32;
+        if self.grounded && self.vertical_velocity < 0.0 {
+            self.vertical_velocity = 0.0;
+        }
+        self.vertical_velocity += world.gravity.y * dt;
+        let desired = glam::Vec3::new(horizontal.x, self.vertical_velocity, horizontal.z) * dt;
+        let (Some(rb), Some(col)) = (world.bodies.get(self.body.0), world.colliders.get(self.collider.0)) else {
+            return;
+        };
+        let shape = col.shared_shape().clone();
+        let pos = *col.position();
+        let filter = rp::QueryFilter::default().exclude_rigid_body(self.body.0);
+        let mut collisions = Vec::new();
+        let movement = self.controller.move_shape(
+            dt,
+            &world.bodies, &world.colliders, &world.query_pipeline,
+            shape.as_ref(), &pos,
+            desired.into(),
+            filter,
+            |c| collisions.push(c),
+        );
+        let next = rb.translation() + movement.translation;
+        self.grounded = movement.grounded;
+        self.sliding = movement.is_sliding_down_slope;
+        // hitting a ceiling stops upward motion
+        if self.vertical_velocity > 0.0 && movement.translation.y < desired.y * 0.5 {
+            self.vertical_velocity = 0.0;
+        }
+        self.controller.solve_character_collision_impulses(
+            dt,
+            &mut world.bodies, &world.colliders, &world.query_pipeline,
+            shape.as_ref(), self.mass,
+            collisions.iter(),
+            filter,
+        );
+        if let Some(rb) = world.bodies.get_mut(self.body.0) {
+            rb.set_next_kinematic_translation(next);
+        }
+    }
+}
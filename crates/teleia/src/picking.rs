@@ -0,0 +1,115 @@
+use crate::{mesh, scene};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: glam::Vec3,
+    /// Always normalized
+    pub dir: glam::Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: glam::Vec3, dir: glam::Vec3) -> Self {
+        Self { origin, dir: dir.normalize() }
+    }
+
+    /// Unproject a point in render coordinates (as passed to Game::mouse_move) through the given camera.
+    /// Assumes a left-handed projection with depth in [0, 1], as produced by glam's *_lh functions.
+    pub fn from_screen(render_dims: glam::Vec2, projection: &glam::Mat4, view: &glam::Mat4, x: f32, y: f32) -> Self {
+        let ndc = glam::Vec2::new(
+            2.0 * x / render_dims.x - 1.0,
+            1.0 - 2.0 * y / render_dims.y,
+        );
+        let inv = (*projection * *view).inverse();
+        let near = inv.project_point3(ndc.extend(0.0));
+        let far = inv.project_point3(ndc.extend(1.0));
+        Self::new(near, far - near)
+    }
+
+    pub fn at(&self, t: f32) -> glam::Vec3 {
+        self.origin + self.dir * t
+    }
+}
+
+/// The closest thing a ray hit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit<T> {
+    pub target: T,
+    /// Distance along the ray
+    pub distance: f32,
+    pub point: glam::Vec3,
+    pub normal: glam::Vec3,
+}
+
+/// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    pub fn from_points<I>(points: I) -> Option<Self> where I: IntoIterator<Item=glam::Vec3> {
+        let mut it = points.into_iter();
+        let first = it.next()?;
+        Some(it.fold(Self { min: first, max: first }, |b, p| Self { min: b.min.min(p), max: b.max.max(p) }))
+    }
+
+    /// Bounds of a mesh's retained CPU data after transforming it (see mesh::Mesh::retain_cpu_data)
+    pub fn from_mesh(m: &mesh::Mesh, transform: glam::Mat4) -> Option<Self> {
+        let cpu = m.cpu.as_ref()?;
+        Self::from_points(cpu.positions.iter().map(|p| transform.transform_point3(*p)))
+    }
+
+    pub fn union(&self, o: &Self) -> Self {
+        Self { min: self.min.min(o.min), max: self.max.max(o.max) }
+    }
+
+    pub fn center(&self) -> glam::Vec3 { (self.min + self.max) / 2.0 }
+    pub fn half_extents(&self) -> glam::Vec3 { (self.max - self.min) / 2.0 }
+
+    /// Slab test, returning the distance along the ray and the normal of the face that was hit.
+    /// A ray starting inside the box hits it at distance 0.
+    pub fn cast(&self, ray: &Ray) -> Option<(f32, glam::Vec3)> {
+        let inv = ray.dir.recip();
+        let t0 = (self.min - ray.origin) * inv;
+        let t1 = (self.max - ray.origin) * inv;
+        let tmin = t0.min(t1);
+        let tmax = t0.max(t1);
+        let enter = tmin.max_element();
+        let exit = tmax.min_element();
+        if exit < enter.max(0.0) { return None; }
+        if enter < 0.0 { return Some((0.0, -ray.dir)); }
+        let axis = if enter == tmin.x { 0 } else if enter == tmin.y { 1 } else { 2 };
+        let mut normal = glam::Vec3::ZERO;
+        normal[axis] = -ray.dir[axis].signum();
+        Some((enter, normal))
+    }
+}
+
+/// Find the closest of several bounding boxes along a ray
+pub fn cast_bounds<T, I>(ray: &Ray, bounds: I) -> Option<Hit<T>> where I: IntoIterator<Item=(T, Aabb)> {
+    bounds.into_iter()
+        .filter_map(|(target, b)| {
+            let (distance, normal) = b.cast(ray)?;
+            Some(Hit { target, distance, point: ray.at(distance), normal })
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// Bounding box of each node with geometry in a scene, using node transforms.
+/// The scene must be loaded with Scene::from_gltf_retained.
+pub fn scene_bounds(s: &scene::Scene, transform: glam::Mat4) -> Vec<(scene::Index, Aabb)> {
+    s.node_transforms().into_iter().filter_map(|(ni, t)| {
+        let o = s.nodes[ni].object.and_then(|i| s.objects.get(i))?;
+        let t = transform.mul_mat4(&t);
+        let b = o.primitives.iter()
+            .filter_map(|p| Aabb::from_mesh(&p.mesh, t))
+            .reduce(|a, b| a.union(&b))?;
+        Some((ni, b))
+    }).collect()
+}
+
+/// Find the scene node whose bounding box a ray hits first
+pub fn cast_scene(ray: &Ray, s: &scene::Scene, transform: glam::Mat4) -> Option<Hit<scene::Index>> {
+    cast_bounds(ray, scene_bounds(s, transform))
+}
@@ -1,3 +1,5 @@
+use glow::HasContext;
+
 use crate::{context, font, mesh, postprocessing, shader, state, texture};
 
 use bitflags::bitflags;
@@ -185,9 +187,9 @@ pub struct RenderTextScreen<'c, 's, 'r, 'str, 'f, A: Assets> {
 }
 impl<'c, 's, 'r, 'str, 'f, A: Assets> RenderTextScreen<'c, 's, 'r, 'str, 'f, A> {
     pub fn render(self) {
+        self.renderer.bind_uber_2d(self.ctx, self.st, UberFlags::TEXTURE_COLOR | UberFlags::VERTEX_COLOR);
         // drawing text might bind the texture
         self.renderer.texture = BoundTexture::None;
-        self.renderer.bind_uber_2d(self.ctx, self.st, UberFlags::TEXTURE_COLOR | UberFlags::VERTEX_COLOR);
         let font = if let Some(font) = self.font { font } else { &self.st.font_default };
         let dims = glam::Vec2::new(font.char_width as f32, font.char_height as f32);
         let fpos = if self.centered {
@@ -214,11 +216,362 @@ impl<'c, 's, 'r, 'str, 'f, A: Assets> RenderTextScreen<'c, 's, 'r, 'str, 'f, A>
     pub fn offset(mut self, offset: glam::Vec2) -> Self { self.offset = Some(offset); self }
 }
 
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct DebugVertex {
+    pos: glam::Vec4,
+    color: glam::Vec4,
+}
+
+const DEBUG_CIRCLE_SEGMENTS: usize = 24;
+
+/// Immediate-mode lines for debugging.
+/// Everything queued during a frame is drawn on top of the scene in a single draw call by flush,
+/// but only while debug mode is on (toggled with Key::Debug).
+pub struct DebugDraw {
+    shader: shader::Shader,
+    vao: glow::VertexArray,
+    vbo: glow::Buffer,
+    /// Pairs of endpoints in world space
+    lines_3d: Vec<(glam::Vec3, glam::Vec4)>,
+    /// Pairs of endpoints in pixels, top left origin
+    lines_2d: Vec<(glam::Vec2, glam::Vec4)>,
+    /// Draw even if debug mode is off
+    pub always: bool,
+}
+impl DebugDraw {
+    pub fn new(ctx: &context::Context) -> Self {
+        let shader = shader::Shader::new_nolib(ctx,
+            include_str!("assets/shaders/debug/vert.glsl"),
+            include_str!("assets/shaders/debug/frag.glsl"),
+        );
+        unsafe {
+            let vao = ctx.gl.create_vertex_array().expect("failed to initialize vao");
+            ctx.gl.bind_vertex_array(Some(vao));
+            let vbo = ctx.gl.create_buffer().expect("failed to create buffer object");
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+            let stride = std::mem::size_of::<DebugVertex>() as i32;
+            ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_VERTEX, 4, glow::FLOAT, false, stride, std::mem::offset_of!(DebugVertex, pos) as _);
+            ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_VERTEX);
+            ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_COLOR, 4, glow::FLOAT, false, stride, std::mem::offset_of!(DebugVertex, color) as _);
+            ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_COLOR);
+            Self {
+                shader,
+                vao, vbo,
+                lines_3d: Vec::new(),
+                lines_2d: Vec::new(),
+                always: false,
+            }
+        }
+    }
+
+    pub fn line(&mut self, a: glam::Vec3, b: glam::Vec3, color: glam::Vec4) {
+        self.lines_3d.push((a, color));
+        self.lines_3d.push((b, color));
+    }
+    pub fn arrow(&mut self, from: glam::Vec3, to: glam::Vec3, color: glam::Vec4) {
+        self.line(from, to, color);
+        let d = to - from;
+        let len = d.length();
+        if len <= f32::EPSILON { return; }
+        let dir = d / len;
+        let side = dir.any_orthonormal_vector() * len * 0.1;
+        let back = to - dir * len * 0.2;
+        self.line(to, back + side, color);
+        self.line(to, back - side, color);
+    }
+    /// Box with the given half extents, transformed (e.g. by a body's transform)
+    pub fn wire_box(&mut self, transform: glam::Mat4, half_extents: glam::Vec3, color: glam::Vec4) {
+        let corner = |i: usize| transform.transform_point3(half_extents * glam::Vec3::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        ));
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 { self.line(corner(i), corner(i | bit), color); }
+            }
+        }
+    }
+    pub fn aabb(&mut self, min: glam::Vec3, max: glam::Vec3, color: glam::Vec4) {
+        let t = glam::Mat4::from_translation((min + max) / 2.0);
+        self.wire_box(t, (max - min) / 2.0, color);
+    }
+    fn circle(&mut self, center: glam::Vec3, u: glam::Vec3, v: glam::Vec3, color: glam::Vec4) {
+        let point = |i: usize| {
+            let a = std::f32::consts::TAU * i as f32 / DEBUG_CIRCLE_SEGMENTS as f32;
+            center + u * a.cos() + v * a.sin()
+        };
+        for i in 0..DEBUG_CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+    /// Three circles, one around each axis
+    pub fn sphere(&mut self, center: glam::Vec3, radius: f32, color: glam::Vec4) {
+        self.circle(center, glam::Vec3::X * radius, glam::Vec3::Y * radius, color);
+        self.circle(center, glam::Vec3::Y * radius, glam::Vec3::Z * radius, color);
+        self.circle(center, glam::Vec3::Z * radius, glam::Vec3::X * radius, color);
+    }
+    /// The transform's X, Y and Z axes in red, green and blue
+    pub fn axes(&mut self, transform: glam::Mat4, size: f32) {
+        let o = transform.transform_point3(glam::Vec3::ZERO);
+        self.arrow(o, transform.transform_point3(glam::Vec3::X * size), glam::Vec4::new(1.0, 0.0, 0.0, 1.0));
+        self.arrow(o, transform.transform_point3(glam::Vec3::Y * size), glam::Vec4::new(0.0, 1.0, 0.0, 1.0));
+        self.arrow(o, transform.transform_point3(glam::Vec3::Z * size), glam::Vec4::new(0.0, 0.0, 1.0, 1.0));
+    }
+
+    pub fn line_2d(&mut self, a: glam::Vec2, b: glam::Vec2, color: glam::Vec4) {
+        self.lines_2d.push((a, color));
+        self.lines_2d.push((b, color));
+    }
+    pub fn arrow_2d(&mut self, from: glam::Vec2, to: glam::Vec2, color: glam::Vec4) {
+        self.line_2d(from, to, color);
+        let d = to - from;
+        let back = to - d * 0.2;
+        let side = d.perp() * 0.1;
+        self.line_2d(to, back + side, color);
+        self.line_2d(to, back - side, color);
+    }
+    /// Outline of a rectangle (pos is top left)
+    pub fn rect_2d(&mut self, pos: glam::Vec2, dims: glam::Vec2, color: glam::Vec4) {
+        let (a, b) = (pos, pos + glam::Vec2::new(dims.x, 0.0));
+        let (c, d) = (pos + dims, pos + glam::Vec2::new(0.0, dims.y));
+        self.line_2d(a, b, color); self.line_2d(b, c, color);
+        self.line_2d(c, d, color); self.line_2d(d, a, color);
+    }
+    pub fn circle_2d(&mut self, center: glam::Vec2, radius: f32, color: glam::Vec4) {
+        let point = |i: usize| {
+            let a = std::f32::consts::TAU * i as f32 / DEBUG_CIRCLE_SEGMENTS as f32;
+            center + glam::Vec2::new(a.cos(), a.sin()) * radius
+        };
+        for i in 0..DEBUG_CIRCLE_SEGMENTS {
+            self.line_2d(point(i), point(i + 1), color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines_3d.clear();
+        self.lines_2d.clear();
+    }
+
+    /// Draw everything queued this frame into the currently bound framebuffer, then clear the queue
+    pub fn flush(&mut self, ctx: &context::Context, st: &state::State) {
+        if (st.debug || self.always) && !(self.lines_3d.is_empty() && self.lines_2d.is_empty()) {
+            // transform on the CPU so that 2D and 3D lines can share one draw
+            let vp = st.projection * st.view();
+            let mut verts: Vec<DebugVertex> = self.lines_3d.iter()
+                .map(|(p, color)| DebugVertex { pos: vp * p.extend(1.0), color: *color })
+                .collect();
+            verts.extend(self.lines_2d.iter().map(|(p, color)| DebugVertex {
+                pos: glam::Vec4::new(
+                    2.0 * p.x / ctx.render_width - 1.0,
+                    1.0 - 2.0 * p.y / ctx.render_height,
+                    0.0, 1.0,
+                ),
+                color: *color,
+            }));
+            self.shader.bind(ctx);
+            unsafe {
+                ctx.gl.bind_vertex_array(Some(self.vao));
+                ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
+                ctx.gl.buffer_data_u8_slice(
+                    glow::ARRAY_BUFFER,
+                    std::slice::from_raw_parts(
+                        verts.as_ptr() as _,
+                        verts.len() * std::mem::size_of::<DebugVertex>(),
+                    ),
+                    glow::STREAM_DRAW,
+                );
+                let depth = ctx.gl.is_enabled(glow::DEPTH_TEST);
+                ctx.gl.disable(glow::DEPTH_TEST);
+                ctx.gl.draw_arrays(glow::LINES, 0, verts.len() as _);
+                if depth { ctx.gl.enable(glow::DEPTH_TEST); }
+            }
+        }
+        self.clear();
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SpriteVertex {
+    pos: glam::Vec2,
+    texcoord: glam::Vec2,
+    color: glam::Vec4,
+    hue: glam::Vec2,
+}
+
+/// Location of the hue attribute in the sprite vertex shader
+const ATTRIB_HUE: u32 = 6;
+/// Sprites per draw call, a full batch is drawn early
+const MAX_SPRITES: usize = 8192;
+
+/// A quad waiting in a SpriteBatch (units are pixels, pos is top left)
+struct Sprite {
+    pos: glam::Vec2,
+    dims: glam::Vec2,
+    rot: glam::Quat,
+    /// Top left and bottom right corners of the region of the texture to draw
+    uv: (glam::Vec2, glam::Vec2),
+    color: glam::Vec4,
+    /// Scale and shift for the hue, (1, 0) leaves it unchanged
+    hue: glam::Vec2,
+}
+
+/// Quads queued by Renderer::sprite_screen and friends.
+/// They are drawn in as few draw calls as possible, one per run of sprites sharing a texture.
+struct SpriteBatch {
+    shader: shader::Shader,
+    vao: glow::VertexArray,
+    vbo: glow::Buffer,
+    vertices: Vec<SpriteVertex>,
+}
+impl SpriteBatch {
+    fn new(ctx: &context::Context) -> Self {
+        let shader = shader::Shader::new_nolib(ctx,
+            include_str!("assets/shaders/sprite/vert.glsl"),
+            include_str!("assets/shaders/sprite/frag.glsl"),
+        );
+        // every quad is two triangles over its four vertices, so the indices never change
+        let indices: Vec<u16> = (0..MAX_SPRITES as u16)
+            .flat_map(|i| [0, 1, 2, 0, 2, 3].map(|o| i * 4 + o))
+            .collect();
+        unsafe {
+            let vao = ctx.gl.create_vertex_array().expect("failed to initialize vao");
+            ctx.gl.bind_vertex_array(Some(vao));
+            let vbo = ctx.gl.create_buffer().expect("failed to create buffer object");
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+            ctx.gl.buffer_data_size(
+                glow::ARRAY_BUFFER,
+                (MAX_SPRITES * 4 * std::mem::size_of::<SpriteVertex>()) as _,
+                glow::DYNAMIC_DRAW,
+            );
+            let stride = std::mem::size_of::<SpriteVertex>() as i32;
+            ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_VERTEX, 2, glow::FLOAT, false, stride, std::mem::offset_of!(SpriteVertex, pos) as _);
+            ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_VERTEX);
+            ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_TEXCOORD, 2, glow::FLOAT, false, stride, std::mem::offset_of!(SpriteVertex, texcoord) as _);
+            ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_TEXCOORD);
+            ctx.gl.vertex_attrib_pointer_f32(mesh::ATTRIB_COLOR, 4, glow::FLOAT, false, stride, std::mem::offset_of!(SpriteVertex, color) as _);
+            ctx.gl.enable_vertex_attrib_array(mesh::ATTRIB_COLOR);
+            ctx.gl.vertex_attrib_pointer_f32(ATTRIB_HUE, 2, glow::FLOAT, false, stride, std::mem::offset_of!(SpriteVertex, hue) as _);
+            ctx.gl.enable_vertex_attrib_array(ATTRIB_HUE);
+            let ebo = ctx.gl.create_buffer().expect("failed to create buffer object");
+            ctx.gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(ebo));
+            ctx.gl.buffer_data_u8_slice(
+                glow::ELEMENT_ARRAY_BUFFER,
+                std::slice::from_raw_parts(indices.as_ptr() as _, std::mem::size_of_val(&indices[..])),
+                glow::STATIC_DRAW,
+            );
+            Self {
+                shader,
+                vao, vbo,
+                vertices: Vec::with_capacity(MAX_SPRITES * 4),
+            }
+        }
+    }
+
+    fn len(&self) -> usize { self.vertices.len() / 4 }
+    fn is_empty(&self) -> bool { self.vertices.is_empty() }
+    fn is_full(&self) -> bool { self.len() >= MAX_SPRITES }
+    fn clear(&mut self) { self.vertices.clear(); }
+
+    fn push(&mut self, st: &state::State, sprite: Sprite) {
+        let Sprite { pos, dims, rot, uv, color, hue } = sprite;
+        // same placement as Shader::set_position_2d_helper, but done here so that sprites can share a draw
+        let half = dims / 2.0;
+        let center = glam::Vec2::new(
+            -st.render_dims.x / 2.0 + pos.x + half.x,
+            st.render_dims.y / 2.0 - pos.y - half.y,
+        );
+        let corner = |x: f32, y: f32| center + (rot * glam::Vec3::new(x * half.x, y * half.y, 0.0)).truncate();
+        let (uvmin, uvmax) = uv;
+        for (pos, texcoord) in [
+            (corner(-1.0, 1.0), uvmin),
+            (corner(1.0, 1.0), glam::Vec2::new(uvmax.x, uvmin.y)),
+            (corner(1.0, -1.0), uvmax),
+            (corner(-1.0, -1.0), glam::Vec2::new(uvmin.x, uvmax.y)),
+        ] {
+            self.vertices.push(SpriteVertex { pos, texcoord, color, hue });
+        }
+    }
+
+    /// Draw and clear everything queued. The caller is responsible for binding the texture.
+    fn draw(&mut self, ctx: &context::Context, st: &state::State, textured: bool) {
+        if self.is_empty() { return; }
+        st.bind_2d(ctx, &self.shader);
+        self.shader.set_i32(ctx, "textured", textured as i32);
+        unsafe {
+            ctx.gl.bind_vertex_array(Some(self.vao));
+            ctx.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
+            ctx.gl.buffer_sub_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                0,
+                std::slice::from_raw_parts(
+                    self.vertices.as_ptr() as _,
+                    self.vertices.len() * std::mem::size_of::<SpriteVertex>(),
+                ),
+            );
+            ctx.gl.draw_elements(glow::TRIANGLES, (self.len() * 6) as _, glow::UNSIGNED_SHORT, 0);
+        }
+        self.clear();
+    }
+}
+
+#[must_use]
+pub struct RenderSpriteScreen<'c, 's, 'r, A: Assets> {
+    ctx: &'c context::Context,
+    st: &'s mut state::State,
+    renderer: &'r mut Renderer<A>,
+    texture: A::Texture,
+    pos: glam::Vec2,
+    dims: Option<glam::Vec2>,
+    rot: Option<glam::Quat>,
+    hue: Option<f32>,
+    region: Option<(glam::Vec2, glam::Vec2)>,
+    col: Option<glam::Vec4>,
+}
+impl<'c, 's, 'r, A: Assets> RenderSpriteScreen<'c, 's, 'r, A> {
+    pub fn render(self) {
+        let (offset, size) = self.region.unwrap_or((glam::Vec2::ZERO, glam::Vec2::ONE));
+        let dims = if let Some(dims) = self.dims { dims } else {
+            let t = self.renderer.assets.texture(self.texture);
+            glam::Vec2::new(t.width as f32, t.height as f32) * size
+        };
+        self.renderer.queue_sprite(self.ctx, self.st, Some(self.texture), Sprite {
+            pos: self.pos,
+            dims,
+            rot: self.rot.unwrap_or(glam::Quat::IDENTITY),
+            uv: (offset, offset + size),
+            color: self.col.unwrap_or(glam::Vec4::ONE),
+            hue: self.hue.map(|h| glam::Vec2::new(0.0, h)).unwrap_or(glam::Vec2::X),
+        });
+    }
+    pub fn dimensions(mut self, dims: glam::Vec2) -> Self { self.dims = Some(dims); self }
+    pub fn rotation(mut self, rot: glam::Quat) -> Self { self.rot = Some(rot); self }
+    pub fn hue(mut self, hue: f32) -> Self { self.hue = Some(hue); self }
+    /// Multiply the texture by a color
+    pub fn color(mut self, col: glam::Vec4) -> Self { self.col = Some(col); self }
+    /// Only draw part of the texture (offset and size are from 0 to 1, offset is top left)
+    pub fn region(mut self, offset: glam::Vec2, size: glam::Vec2) -> Self { self.region = Some((offset, size)); self }
+    /// Only draw cell (x, y) of a sprite sheet with xinc by yinc cells, like Renderer::set_texture_offset
+    pub fn frame(self, xinc: i32, yinc: i32, x: i32, y: i32) -> Self {
+        let size = glam::Vec2::new(1.0 / xinc as f32, 1.0 / yinc as f32);
+        let offset = glam::Vec2::new((x % xinc) as f32, (y % yinc) as f32) * size;
+        self.region(offset, size)
+    }
+}
+
 pub struct Renderer<A: Assets> {
     pub assets: A,
+    pub debug: DebugDraw,
     shader_uber: shader::Shader,
     shader: BoundShader<A>,
     texture: BoundTexture<A>,
+    sprites: SpriteBatch,
+    /// Texture of the queued sprites, None if they are plain colors
+    sprites_texture: Option<A::Texture>,
+    /// Whether we've already warned about sprites left queued at the end of a frame
+    warned_unflushed: bool,
 }
 impl<A: Assets> Renderer<A> {
     pub fn new<F>(ctx: &context::Context, st: &mut state::State, f: F) -> Self
@@ -231,21 +584,28 @@ impl<A: Assets> Renderer<A> {
         shader_uber.set_i32(ctx, "texture_normal", 1);
         Self {
             assets: f(ctx, st),
+            debug: DebugDraw::new(ctx),
             shader_uber,
             shader: BoundShader::None,
             texture: BoundTexture::None,
+            sprites: SpriteBatch::new(ctx),
+            sprites_texture: None,
+            warned_unflushed: false,
         }
     }
-    pub fn unbind_texture(&mut self, _ctx: &context::Context, _st: &mut state::State) {
+    pub fn unbind_texture(&mut self, ctx: &context::Context, st: &mut state::State) {
+        self.flush_sprites(ctx, st);
         self.texture = BoundTexture::None;
     }
-    pub fn bind_texture(&mut self, ctx: &context::Context, _st: &mut state::State, texture: A::Texture) {
+    pub fn bind_texture(&mut self, ctx: &context::Context, st: &mut state::State, texture: A::Texture) {
+        self.flush_sprites(ctx, st);
         if self.texture != BoundTexture::Texture(texture) {
             self.assets.texture(texture).bind(ctx);
             self.texture = BoundTexture::Texture(texture);
         }
     }
-    pub fn bind_material(&mut self, ctx: &context::Context, _st: &mut state::State, mat: A::Material) {
+    pub fn bind_material(&mut self, ctx: &context::Context, st: &mut state::State, mat: A::Material) {
+        self.flush_sprites(ctx, st);
         if self.texture != BoundTexture::Material(mat) {
             self.assets.material(mat).bind(ctx);
             self.texture = BoundTexture::Material(mat);
@@ -262,6 +622,7 @@ impl<A: Assets> Renderer<A> {
         ctx: &context::Context, st: &mut state::State,
         flags: UberFlags, mode: ShaderMode,
     ) {
+        self.draw_sprites(ctx, st);
         if let BoundShader::Uber(f, sm) = self.shader && f == flags && sm == mode { return }
         match mode {
             ShaderMode::TwoDimension => st.bind_2d(ctx, &self.shader_uber),
@@ -275,6 +636,7 @@ impl<A: Assets> Renderer<A> {
         ctx: &context::Context, st: &mut state::State,
         shader: A::Shader, mode: ShaderMode,
     ) {
+        self.draw_sprites(ctx, st);
         if let BoundShader::Shader(s, sm) = self.shader && s == shader && sm == mode { return }
         match mode {
             ShaderMode::TwoDimension => st.bind_2d(ctx, self.assets.shader(shader)),
@@ -371,10 +733,55 @@ impl<A: Assets> Renderer<A> {
     ) {
         self.texture = BoundTexture::None;
         self.shader = BoundShader::None;
+        if !self.sprites.is_empty() && !self.warned_unflushed {
+            log::warn!("sprites queued last frame were never drawn, call Renderer::end_frame after drawing");
+            self.warned_unflushed = true;
+        }
+        self.sprites.clear();
         ctx.clear_color(clear_color);
         ctx.clear();
     }
 
+    /// Draw all queued sprites and clear the batch, leaving the current shader bound.
+    /// This happens automatically whenever the renderer binds a shader or texture,
+    /// but is needed before drawing with anything else (e.g. a tiled::LevelRenderer) to keep the order right.
+    pub fn flush_sprites(&mut self, ctx: &context::Context, st: &mut state::State) {
+        if self.sprites.is_empty() { return; }
+        let prev = self.shader;
+        self.draw_sprites(ctx, st);
+        match prev {
+            BoundShader::Uber(flags, mode) => self.bind_uber(ctx, st, flags, mode),
+            BoundShader::Shader(shader, mode) => self.bind_shader(ctx, st, shader, mode),
+            BoundShader::None => {},
+        }
+    }
+    /// Draw all queued sprites and clear the batch, leaving the sprite shader bound
+    fn draw_sprites(&mut self, ctx: &context::Context, st: &mut state::State) {
+        if self.sprites.is_empty() { return; }
+        if let Some(texture) = self.sprites_texture && self.texture != BoundTexture::Texture(texture) {
+            self.assets.texture(texture).bind(ctx);
+            self.texture = BoundTexture::Texture(texture);
+        }
+        self.sprites.draw(ctx, st, self.sprites_texture.is_some());
+        self.shader = BoundShader::None;
+    }
+    fn queue_sprite(&mut self, ctx: &context::Context, st: &mut state::State, texture: Option<A::Texture>, sprite: Sprite) {
+        if !self.sprites.is_empty() && (self.sprites_texture != texture || self.sprites.is_full()) {
+            self.flush_sprites(ctx, st);
+        }
+        self.sprites_texture = texture;
+        self.sprites.push(st, sprite);
+    }
+
+    /// Finish drawing the frame: draws any queued sprites and anything queued on the debug drawer.
+    /// Call this at the end of Game::render whenever sprite_screen or the debug drawer is used,
+    /// otherwise whatever is still queued is never drawn.
+    pub fn end_frame(&mut self, ctx: &context::Context, st: &mut state::State) {
+        self.draw_sprites(ctx, st);
+        self.debug.flush(ctx, st);
+        self.shader = BoundShader::None;
+    }
+
     /// Enable the given postprocessing effect for the current frame
     pub fn postprocessing_effect<'s, 'r>(&'r mut self,
         _ctx: &context::Context, st: &'s mut state::State,
@@ -428,6 +835,40 @@ impl<A: Assets> Renderer<A> {
         }
     }
 
+    /// Like texture_screen, but batched: consecutive sprites with the same texture are drawn together.
+    /// Sprites are drawn when a different texture is used, when the renderer binds another shader or texture,
+    /// or on flush_sprites. Call end_frame once the frame is drawn so the last batch isn't lost.
+    pub fn sprite_screen<'c, 's, 'r>(&'r mut self, ctx: &'c context::Context, st: &'s mut state::State,
+        pos: glam::Vec2,
+        texture: A::Texture,
+    ) -> RenderSpriteScreen<'c, 's, 'r, A> {
+        RenderSpriteScreen {
+            ctx, st, renderer: self,
+            texture, pos,
+            dims: None,
+            rot: None,
+            hue: None,
+            region: None,
+            col: None,
+        }
+    }
+
+    /// Like color_screen, but batched (see sprite_screen)
+    pub fn sprite_color_screen(&mut self,
+        ctx: &context::Context, st: &mut state::State,
+        color: glam::Vec4,
+        pos: glam::Vec2,
+        dims: glam::Vec2,
+    ) {
+        self.queue_sprite(ctx, st, None, Sprite {
+            pos, dims,
+            rot: glam::Quat::IDENTITY,
+            uv: (glam::Vec2::ZERO, glam::Vec2::ONE),
+            color,
+            hue: glam::Vec2::X,
+        });
+    }
+
     /// Common case: text in the default font (units are pixels, pos is top left)
     pub fn text_screen<'c, 's, 'r, 'str, 'f>(&'r mut self,
         ctx: &'c context::Context, st: &'s mut state::State,
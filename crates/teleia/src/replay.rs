@@ -0,0 +1,131 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{gamepad, state, Erm, Tick};
+
+/// Everything that the platform layer can feed into the state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    KeyPressed(state::Keycode),
+    KeyReleased(state::Keycode),
+    GamepadPressed(gamepad::Button),
+    GamepadReleased(gamepad::Button),
+    VirtualKeyPressed(state::Key),
+    VirtualKeyReleased(state::Key),
+    MouseMoved(f32, f32),
+    MousePressed(state::MouseButton),
+    MouseReleased(state::MouseButton),
+    MouseScrolled(f32, f32),
+    MouseDelta(f32, f32),
+    TextInput(char),
+    TextEdit(state::TextEdit),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    /// Seed for the state's RNG at the start of the recording
+    pub seed: u64,
+    /// Events, tagged with the number of ticks since the recording started
+    pub events: Vec<(Tick, Event)>,
+}
+impl Recording {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, events: Vec::new() }
+    }
+    pub fn to_bytes(&self) -> Erm<Vec<u8>> {
+        Ok(bincode::serde::encode_to_vec(self, bincode::config::standard())?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Erm<Self> {
+        let (ret, _) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+        Ok(ret)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_file<P>(&self, path: P) -> Erm<()> where P: AsRef<std::path::Path> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_file<P>(path: P) -> Erm<Self> where P: AsRef<std::path::Path> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+pub struct Recorder {
+    start: Tick,
+    recording: Recording,
+}
+impl Recorder {
+    pub fn new(start: Tick, seed: u64) -> Self {
+        Self { start, recording: Recording::new(seed) }
+    }
+    pub fn record(&mut self, tick: Tick, ev: Event) {
+        self.recording.events.push((tick - self.start, ev));
+    }
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+pub struct Playback {
+    start: Tick,
+    recording: Recording,
+    next: usize,
+}
+impl Playback {
+    pub fn new(start: Tick, recording: Recording) -> Self {
+        Self { start, recording, next: 0 }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.events.len()
+    }
+    /// Remove and return all events that should be delivered at the given tick
+    pub fn take_due(&mut self, tick: Tick) -> Vec<Event> {
+        let offset = tick - self.start;
+        let mut ret = Vec::new();
+        while let Some((t, ev)) = self.recording.events.get(self.next) && *t <= offset {
+            ret.push(ev.clone());
+            self.next += 1;
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_round_trip() {
+        let mut rec = Recorder::new(100, 42);
+        rec.record(100, Event::VirtualKeyPressed(state::Key::A));
+        rec.record(103, Event::MouseMoved(1.5, -2.0));
+        rec.record(103, Event::TextInput('é'));
+        rec.record(110, Event::MousePressed(state::MouseButton::Extra(3)));
+        let recording = rec.finish();
+        let decoded = Recording::from_bytes(&recording.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.seed, 42);
+        assert_eq!(format!("{:?}", decoded.events), format!("{:?}", recording.events));
+        assert_eq!(decoded.events[1].0, 3);
+    }
+
+    #[test]
+    fn playback_delivers_events_on_their_tick() {
+        let mut rec = Recording::new(0);
+        rec.events = vec![
+            (0, Event::VirtualKeyPressed(state::Key::Up)),
+            (2, Event::VirtualKeyReleased(state::Key::Up)),
+            (2, Event::TextEdit(state::TextEdit::Enter)),
+        ];
+        let mut pb = Playback::new(10, rec);
+        assert_eq!(pb.take_due(10).len(), 1);
+        assert!(pb.take_due(11).is_empty());
+        assert!(!pb.is_finished());
+        assert_eq!(pb.take_due(12).len(), 2);
+        assert!(pb.is_finished());
+    }
+
+    #[test]
+    fn truncated_recording_is_rejected() {
+        let bytes = Recording::new(7).to_bytes().unwrap();
+        assert!(Recording::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
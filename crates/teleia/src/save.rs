@@ -1,47 +1,68 @@
 #[cfg(target_arch = "wasm32")]
 use base64::prelude::*;
 
-#[cfg(target_arch = "wasm32")]
+use crate::utils;
+
+/// Slot used by save and load
+const MAIN_SLOT: &str = "teleia";
+
 pub fn save<W>(id: &str, data: &W) where W: serde::Serialize {
-    let window = web_sys::window().expect("failed to get window object");
-    let storage = window.local_storage()
-        .expect("failed to get local storage")
-        .expect("local storage not present");
-    let key = format!("{}_save", id);
-    let val = bincode::serde::encode_to_vec(data, bincode::config::standard()).expect("failed to serialize save");
-    let str = BASE64_STANDARD.encode(&val);
-    storage.set_item(&key, &str).expect("failed to set save");
+    save_slot(id, MAIN_SLOT, data).expect("failed to write save");
 }
 
-#[cfg(target_arch = "wasm32")]
 pub fn load<W>(id: &str) -> Option<W> where W: serde::de::DeserializeOwned {
-    let window = web_sys::window().expect("failed to get window object");
-    let storage = window.local_storage()
-        .expect("failed to get local storage")
-        .expect("local storage not present");
-    let key = format!("{}_save", id);
-    let s = storage.get_item(&key).expect("failed to get save").expect("save not present");
-    let bytes = BASE64_STANDARD.decode(&s).expect("failed to decode base64 for save");
-    let (ret, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
-        .expect("failed to deserialize save");
+    load_slot(id, MAIN_SLOT)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage_key(id: &str, slot: &str) -> String {
+    if slot == MAIN_SLOT { format!("{}_save", id) } else { format!("{}_{}_save", id, slot) }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage() -> utils::Erm<web_sys::Storage> {
+    let Some(window) = web_sys::window() else { return utils::erm_msg("failed to get window object") };
+    match window.local_storage() {
+        Ok(Some(s)) => Ok(s),
+        _ => utils::erm_msg("local storage not present"),
+    }
+}
+
+/// Like save, but stored under a named slot alongside (rather than replacing) the main save
+#[cfg(target_arch = "wasm32")]
+pub fn save_slot<W>(id: &str, slot: &str, data: &W) -> utils::Erm<()> where W: serde::Serialize {
+    let val = bincode::serde::encode_to_vec(data, bincode::config::standard())?;
+    if storage()?.set_item(&storage_key(id, slot), &BASE64_STANDARD.encode(&val)).is_err() {
+        return utils::erm_msg("failed to set save");
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load_slot<W>(id: &str, slot: &str) -> Option<W> where W: serde::de::DeserializeOwned {
+    let s = storage().ok()?.get_item(&storage_key(id, slot)).ok()??;
+    let bytes = BASE64_STANDARD.decode(&s).ok()?;
+    let (ret, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).ok()?;
     Some(ret)
 }
 
+/// Like save, but stored under a named slot alongside (rather than replacing) the main save
 #[cfg(not(target_arch = "wasm32"))]
-pub fn save<W>(id: &str, data: &W) where W: serde::Serialize {
-    let pd = directories::ProjectDirs::from("", "milkfat", id).expect("failed to get save directory");
-    let _ = std::fs::create_dir_all(pd.data_dir());
-    let path = pd.data_dir().join("teleia.save");
-    let mut file = std::fs::File::create(&path).expect("failed to open save file");
-    bincode::serde::encode_into_std_write(data, &mut file, bincode::config::standard())
-        .expect("failed to write save file");
+pub fn save_slot<W>(id: &str, slot: &str, data: &W) -> utils::Erm<()> where W: serde::Serialize {
+    let Some(pd) = directories::ProjectDirs::from("", "milkfat", id) else {
+        return utils::erm_msg("failed to get save directory");
+    };
+    std::fs::create_dir_all(pd.data_dir())?;
+    let path = pd.data_dir().join(format!("{}.save", slot));
+    let mut file = std::fs::File::create(&path)?;
+    bincode::serde::encode_into_std_write(data, &mut file, bincode::config::standard())?;
+    Ok(())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn load<W>(id: &str) -> Option<W> where W: serde::de::DeserializeOwned {
-    let pd = directories::ProjectDirs::from("", "milkfat", id).expect("failed to get save directory");
-    let _ = std::fs::create_dir_all(pd.data_dir());
-    let path = pd.data_dir().join("teleia.save");
+pub fn load_slot<W>(id: &str, slot: &str) -> Option<W> where W: serde::de::DeserializeOwned {
+    let pd = directories::ProjectDirs::from("", "milkfat", id)?;
+    let path = pd.data_dir().join(format!("{}.save", slot));
     let mut file = std::fs::File::open(&path).ok()?;
     bincode::serde::decode_from_std_read(&mut file, bincode::config::standard()).ok()
 }
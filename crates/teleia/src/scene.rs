@@ -87,6 +87,13 @@ impl Scene {
         )
     }
     pub fn from_gltf(ctx: &context::Context, bytes: &[u8]) -> Self {
+        Self::from_gltf_options(ctx, bytes, false)
+    }
+    /// Like from_gltf, but every primitive's mesh keeps its positions and indices (see mesh::Mesh::retain_cpu_data)
+    pub fn from_gltf_retained(ctx: &context::Context, bytes: &[u8]) -> Self {
+        Self::from_gltf_options(ctx, bytes, true)
+    }
+    fn from_gltf_options(ctx: &context::Context, bytes: &[u8], retain: bool) -> Self {
         let (gltf, buffers, images) = gltf::import_slice(bytes).expect("failed to parse GLTF");
         let get_buffer_data = |b: gltf::Buffer| {
             buffers.get(b.index()).map(|gltf::buffer::Data(bytes)| bytes.as_slice())
@@ -206,6 +213,12 @@ impl Scene {
                             index_count: indices.len(),
                             index_type: glow::UNSIGNED_INT,
                             index_offset: 0,
+                            cpu: if retain {
+                                Some(mesh::CpuData {
+                                    positions: vertices.iter().map(|v| v.pos).collect(),
+                                    indices: indices.clone(),
+                                })
+                            } else { None },
                         },
                         material: p.material().index().unwrap(),
                     }
@@ -339,6 +352,24 @@ impl Scene {
         ret
     }
 
+    /// World transform of every node reachable from the scene's root nodes
+    pub fn node_transforms(&self) -> Vec<(Index, glam::Mat4)> {
+        let mut ret = Vec::new();
+        let mut q: VecDeque<(Index, glam::Mat4)> = VecDeque::new();
+        for sn in &self.scene_nodes {
+            q.push_back((*sn, glam::Mat4::IDENTITY));
+        }
+        while let Some((ni, m)) = q.pop_front() {
+            let n = &self.nodes[ni];
+            let t = m.mul_mat4(&n.transform);
+            ret.push((ni, t));
+            for ci in &n.children {
+                q.push_back((*ci, t));
+            }
+        }
+        ret
+    }
+
     fn render_node(&self, ctx: &context::Context, shader: &shader::Shader, n: &Node) {
         if let Some(o) = n.object.and_then(|i| self.objects.get(i)) {
             if let Some(s) = n.skin.and_then(|i| self.skins.get(i)) {
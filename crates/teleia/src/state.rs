@@ -1,6 +1,5 @@
 #![allow(dead_code, unused_variables)]
-use std::{collections::HashMap, fmt::Display};
-use bimap::BiHashMap;
+use std::{collections::{HashMap, HashSet}, fmt::Display};
 use enum_map::{enum_map, Enum, EnumMap};
 use serde::{Serialize, Deserialize};
 use strum::EnumIter;
@@ -8,11 +7,21 @@ use strum::EnumIter;
 #[cfg(not(target_arch = "wasm32"))]
 use glow::HasContext;
 
-use crate::{audio, context, font, framebuffer, mesh, postprocessing, shader, utils};
+use rand::SeedableRng;
+
+use crate::{audio, capture, context, font, framebuffer, gamepad, mesh, picking, postprocessing, replay, save, shader, utils};
 
 pub type Tick = u64;
 
-pub const DELTA_TIME: f64 = 0.016; // todo
+/// Value of `State::tick` before the first update.
+/// Starting above 0 allows us to use "0" as the default time for various animation starts on entities
+/// without having them all play at game start.
+pub const INITIAL_TICK: Tick = 1000;
+
+/// Default number of calls to `Game::update` per second
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+/// Upper bound on catch-up updates per frame (e.g. after a hitch or a long stall)
+pub const MAX_UPDATES_PER_FRAME: u32 = 8;
 
 pub const ORTH_WIDTH: f32 = 7.55869;
 pub const ORTH_HEIGHT: f32 = 5.03913;
@@ -26,12 +35,36 @@ pub trait Game {
     ) -> HashMap<String, audio::Audio> {
         HashMap::new()
     }
+    /// Identifier for this game's saved data (e.g. keybindings and captures).
+    /// If set, the "default" keybinding profile is loaded at startup and saved whenever bindings change.
+    fn save_id(&self) -> Option<&str> { None }
     fn keybindings_were_reset(&mut self, ctx: &context::Context, st: &mut State) -> utils::Erm<()> { Ok(()) }
     fn mouse_move(&mut self, ctx: &context::Context, st: &mut State, x: i32, y: i32) -> utils::Erm<()> { Ok(()) }
     fn mouse_press(&mut self, ctx: &context::Context, st: &mut State) -> utils::Erm<()> { Ok(()) }
     fn mouse_released(&mut self, ctx: &context::Context, st: &mut State) -> utils::Erm<()> { Ok(()) }
+    /// Like mouse_press, but also receives which button was pressed
+    fn mouse_button_press(&mut self, ctx: &context::Context, st: &mut State, button: MouseButton) -> utils::Erm<()> {
+        self.mouse_press(ctx, st)
+    }
+    /// Like mouse_released, but also receives which button was released
+    fn mouse_button_released(&mut self, ctx: &context::Context, st: &mut State, button: MouseButton) -> utils::Erm<()> {
+        self.mouse_released(ctx, st)
+    }
+    /// Scroll wheel movement in lines (positive y is away from the user)
+    fn mouse_scroll(&mut self, ctx: &context::Context, st: &mut State, dx: f32, dy: f32) -> utils::Erm<()> { Ok(()) }
+    /// Raw mouse motion, only delivered while the mouse is locked (see Context::lock_mouse)
+    fn mouse_delta(&mut self, ctx: &context::Context, st: &mut State, dx: f32, dy: f32) -> utils::Erm<()> { Ok(()) }
+    /// A typed character, after keyboard layout and composition (control characters are filtered out)
+    fn text_input(&mut self, ctx: &context::Context, st: &mut State, c: char) -> utils::Erm<()> { Ok(()) }
+    /// An editing key for text entry, delivered on press and on key repeat
+    fn text_edit(&mut self, ctx: &context::Context, st: &mut State, e: TextEdit) -> utils::Erm<()> { Ok(()) }
     fn update(&mut self, ctx: &context::Context, st: &mut State) -> utils::Erm<()> { Ok(()) }
     fn render(&mut self, ctx: &context::Context, st: &mut State) -> utils::Erm<()> { Ok(()) }
+    /// Like render, but also receives how far we are between the last update and the next (in [0, 1)).
+    /// Games that want smooth motion at high refresh rates should interpolate using alpha.
+    fn render_interpolated(&mut self, ctx: &context::Context, st: &mut State, alpha: f32) -> utils::Erm<()> {
+        self.render(ctx, st)
+    }
 }
 
 #[derive(Debug, Enum, EnumIter, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -45,6 +78,10 @@ pub enum Key {
 pub struct Keys {
     pub pressed: EnumMap<Key, bool>,
     pub new: EnumMap<Key, bool>,
+    /// Physical inputs currently held down
+    pub held: HashSet<Input>,
+    /// Virtual keys currently held by the gamepad's stick
+    pub stick: EnumMap<Key, bool>,
 }
 impl Keys {
     pub fn new() -> Self { Self::default() }
@@ -148,21 +185,202 @@ impl<'de> Deserialize<'de> for Keycode {
     }
 }
 
+/// Keys handled by the engine itself rather than the game.
+/// These are intercepted before input recording, so replays never contain (or re-trigger) them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hotkey {
+    /// Reset keybindings to the defaults
+    ResetBindings,
+    Screenshot,
+    #[cfg(not(target_arch = "wasm32"))]
+    Video,
+    Gif,
+}
+impl Hotkey {
+    #[cfg(target_arch = "wasm32")]
+    fn from_keycode(key: Keycode) -> Option<Self> {
+        match key.kc {
+            winit::keyboard::KeyCode::F12 => Some(Self::ResetBindings),
+            winit::keyboard::KeyCode::F2 => Some(Self::Screenshot),
+            winit::keyboard::KeyCode::F4 => Some(Self::Gif),
+            _ => None,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_keycode(key: Keycode) -> Option<Self> {
+        match key.kc {
+            glfw::Key::F12 => Some(Self::ResetBindings),
+            glfw::Key::F2 => Some(Self::Screenshot),
+            glfw::Key::F3 => Some(Self::Video),
+            glfw::Key::F4 => Some(Self::Gif),
+            _ => None,
+        }
+    }
+}
+
+/// Keys that edit text rather than insert it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TextEdit {
+    Backspace, Delete,
+    Left, Right, Home, End,
+    Enter,
+}
+impl TextEdit {
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_keycode(key: Keycode) -> Option<Self> {
+        match key.kc {
+            winit::keyboard::KeyCode::Backspace => Some(Self::Backspace),
+            winit::keyboard::KeyCode::Delete => Some(Self::Delete),
+            winit::keyboard::KeyCode::ArrowLeft => Some(Self::Left),
+            winit::keyboard::KeyCode::ArrowRight => Some(Self::Right),
+            winit::keyboard::KeyCode::Home => Some(Self::Home),
+            winit::keyboard::KeyCode::End => Some(Self::End),
+            winit::keyboard::KeyCode::Enter | winit::keyboard::KeyCode::NumpadEnter => Some(Self::Enter),
+            _ => None,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_keycode(key: Keycode) -> Option<Self> {
+        match key.kc {
+            glfw::Key::Backspace => Some(Self::Backspace),
+            glfw::Key::Delete => Some(Self::Delete),
+            glfw::Key::Left => Some(Self::Left),
+            glfw::Key::Right => Some(Self::Right),
+            glfw::Key::Home => Some(Self::Home),
+            glfw::Key::End => Some(Self::End),
+            glfw::Key::Enter | glfw::Key::KpEnter => Some(Self::Enter),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left, Right, Middle,
+    /// Additional buttons (e.g. back/forward), numbered from 0
+    Extra(u8),
+}
+impl MouseButton {
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(b: winit::event::MouseButton) -> Self {
+        match b {
+            winit::event::MouseButton::Left => Self::Left,
+            winit::event::MouseButton::Right => Self::Right,
+            winit::event::MouseButton::Middle => Self::Middle,
+            winit::event::MouseButton::Back => Self::Extra(0),
+            winit::event::MouseButton::Forward => Self::Extra(1),
+            // Extra(0) and Extra(1) are taken by back and forward
+            winit::event::MouseButton::Other(n) => Self::Extra(n.saturating_add(2).min(u8::MAX as u16) as u8),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(b: glfw::MouseButton) -> Self {
+        match b {
+            glfw::MouseButton::Button1 => Self::Left,
+            glfw::MouseButton::Button2 => Self::Right,
+            glfw::MouseButton::Button3 => Self::Middle,
+            other => Self::Extra((other as i32 - 3) as u8),
+        }
+    }
+}
+
+/// A physical input that can be bound to a virtual key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+    Keyboard(Keycode),
+    Mouse(MouseButton),
+    Gamepad(gamepad::Button),
+}
+impl Input {
+    /// Do both inputs come from the same kind of device?
+    pub fn same_device(&self, o: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(o)
+    }
+}
+impl Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Keyboard(kc) => write!(f, "{}", kc),
+            Self::Mouse(MouseButton::Extra(n)) => write!(f, "Mouse{}", n + 4),
+            Self::Mouse(b) => write!(f, "Mouse{:?}", b),
+            Self::Gamepad(b) => write!(f, "Pad{:?}", b),
+        }
+    }
+}
+
+/// Many-to-one map from physical inputs to virtual keys (e.g. both W and Up can drive Key::Up)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Keybindings {
+    entries: Vec<(Input, Key)>,
+}
+impl Keybindings {
+    pub fn new() -> Self { Self::default() }
+    pub fn get(&self, i: &Input) -> Option<Key> {
+        self.entries.iter().find(|(ei, _)| ei == i).map(|(_, k)| *k)
+    }
+    /// All inputs bound to the given key, in the order they were bound
+    pub fn inputs_for(&self, k: Key) -> impl Iterator<Item=&Input> {
+        self.entries.iter().filter(move |(_, ek)| *ek == k).map(|(i, _)| i)
+    }
+    /// Bind an input to a key. An input drives at most one key, so any previous binding for it is replaced.
+    pub fn bind(&mut self, i: Input, k: Key) {
+        self.entries.retain(|(ei, _)| *ei != i);
+        self.entries.push((i, k));
+    }
+    /// Remove all bindings for the given key
+    pub fn unbind(&mut self, k: Key) {
+        self.entries.retain(|(_, ek)| *ek != k);
+    }
+    /// Remove the bindings for the given key that come from the same kind of device as the given input
+    pub fn unbind_device(&mut self, k: Key, device: &Input) {
+        self.entries.retain(|(ei, ek)| *ek != k || !ei.same_device(device));
+    }
+}
+impl FromIterator<(Input, Key)> for Keybindings {
+    fn from_iter<I>(iter: I) -> Self where I: IntoIterator<Item=(Input, Key)> {
+        let mut ret = Self::new();
+        for (i, k) in iter { ret.bind(i, k); }
+        ret
+    }
+}
+
 pub struct State {
     pub tick: Tick,
-    pub nextframe: Timestamp,
+    pub tick_rate: f64,
+    pub last_frame: Timestamp,
+    pub accumulator: f64,
+    pub alpha: f32,
     pub fps: u32,
     pub frames_this_second: u32,
     pub start_this_second: Timestamp,
 
     pub rebinding: Option<Key>,
-    pub keybindings: BiHashMap<Keycode, Key>,
+    pub rebinding_additional: bool,
+    pub keybindings: Keybindings,
+    pub keybinding_profile: String,
+    pub save_id: Option<String>,
     pub keys: Keys,
+    /// Toggled by Key::Debug; enables debug drawing (see renderer::DebugDraw)
+    pub debug: bool,
+    /// Raw mouse motion accumulated since the last tick (only while the mouse is locked)
+    pub mouse_delta: glam::Vec2,
+    /// Scroll wheel movement accumulated since the last tick
+    pub mouse_scroll: glam::Vec2,
+    pub gamepad: gamepad::Gamepad,
+    pub rng: rand::rngs::SmallRng,
+    pub recorder: Option<replay::Recorder>,
+    pub playback: Option<replay::Playback>,
 
     pub screen: framebuffer::Framebuffer,
     pub render_framebuffer: framebuffer::Framebuffer,
     pub render_dims: glam::Vec2,
     pub postprocessing: postprocessing::Pipeline,
+    pub screenshot_request: Option<capture::Source>,
+    /// Copy of the screen for capturing the final output, created on first use
+    pub screen_capture: Option<framebuffer::Framebuffer>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub video: Option<capture::VideoRecorder>,
+    pub gif: Option<capture::GifRecorder>,
     pub shader_text_bitmap: shader::Shader,
     pub mesh_square: mesh::Mesh,
     pub font_default: font::Bitmap,
@@ -183,47 +401,76 @@ pub fn now(ctx: &context::Context) -> Timestamp {
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn now(ctx: &context::Context) -> Timestamp {
-    let elapsed = ctx.start_instant.elapsed();
-    let ms = elapsed.as_millis();
-    (ms as f64) / 1000.0
+    ctx.start_instant.elapsed().as_secs_f64()
+}
+
+fn default_gamepad_keybindings() -> Vec<(Input, Key)> {
+    vec![
+        (Input::Gamepad(gamepad::Button::DPadUp), Key::Up),
+        (Input::Gamepad(gamepad::Button::DPadDown), Key::Down),
+        (Input::Gamepad(gamepad::Button::DPadLeft), Key::Left),
+        (Input::Gamepad(gamepad::Button::DPadRight), Key::Right),
+        (Input::Gamepad(gamepad::Button::A), Key::A),
+        (Input::Gamepad(gamepad::Button::B), Key::B),
+        (Input::Gamepad(gamepad::Button::X), Key::X),
+        (Input::Gamepad(gamepad::Button::Y), Key::Y),
+        (Input::Gamepad(gamepad::Button::LeftBumper), Key::L),
+        (Input::Gamepad(gamepad::Button::RightBumper), Key::R),
+        (Input::Gamepad(gamepad::Button::Start), Key::Start),
+        (Input::Gamepad(gamepad::Button::Back), Key::Select),
+    ]
 }
 
 #[cfg(target_arch = "wasm32")]
-pub fn default_keybindings() -> BiHashMap<Keycode, Key> {
-    BiHashMap::from_iter(vec![
-        (Keycode::new(winit::keyboard::KeyCode::KeyW), Key::Up),
-        (Keycode::new(winit::keyboard::KeyCode::KeyS), Key::Down),
-        (Keycode::new(winit::keyboard::KeyCode::KeyA), Key::Left),
-        (Keycode::new(winit::keyboard::KeyCode::KeyD), Key::Right),
-        (Keycode::new(winit::keyboard::KeyCode::Digit1), Key::A),
-        (Keycode::new(winit::keyboard::KeyCode::Digit2), Key::B),
-        (Keycode::new(winit::keyboard::KeyCode::Digit3), Key::X),
-        (Keycode::new(winit::keyboard::KeyCode::Digit4), Key::Y),
-        (Keycode::new(winit::keyboard::KeyCode::KeyQ), Key::L),
-        (Keycode::new(winit::keyboard::KeyCode::KeyE), Key::R),
-        (Keycode::new(winit::keyboard::KeyCode::Tab), Key::Start),
-        (Keycode::new(winit::keyboard::KeyCode::Space), Key::Select),
-        (Keycode::new(winit::keyboard::KeyCode::Backquote), Key::Debug),
-    ])
+pub fn default_keybindings() -> Keybindings {
+    let kb = |kc| Input::Keyboard(Keycode::new(kc));
+    vec![
+        (kb(winit::keyboard::KeyCode::KeyW), Key::Up),
+        (kb(winit::keyboard::KeyCode::KeyS), Key::Down),
+        (kb(winit::keyboard::KeyCode::KeyA), Key::Left),
+        (kb(winit::keyboard::KeyCode::KeyD), Key::Right),
+        (kb(winit::keyboard::KeyCode::ArrowUp), Key::Up),
+        (kb(winit::keyboard::KeyCode::ArrowDown), Key::Down),
+        (kb(winit::keyboard::KeyCode::ArrowLeft), Key::Left),
+        (kb(winit::keyboard::KeyCode::ArrowRight), Key::Right),
+        (kb(winit::keyboard::KeyCode::Digit1), Key::A),
+        (kb(winit::keyboard::KeyCode::Digit2), Key::B),
+        (kb(winit::keyboard::KeyCode::Digit3), Key::X),
+        (kb(winit::keyboard::KeyCode::Digit4), Key::Y),
+        (kb(winit::keyboard::KeyCode::KeyQ), Key::L),
+        (kb(winit::keyboard::KeyCode::KeyE), Key::R),
+        (kb(winit::keyboard::KeyCode::Tab), Key::Start),
+        (kb(winit::keyboard::KeyCode::Space), Key::Select),
+        (kb(winit::keyboard::KeyCode::Backquote), Key::Debug),
+    ].into_iter().chain(default_gamepad_keybindings()).collect()
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn default_keybindings() -> BiHashMap<Keycode, Key> {
-    BiHashMap::from_iter(vec![
-        (Keycode::new(glfw::Key::W), Key::Up),
-        (Keycode::new(glfw::Key::S), Key::Down),
-        (Keycode::new(glfw::Key::A), Key::Left),
-        (Keycode::new(glfw::Key::D), Key::Right),
-        (Keycode::new(glfw::Key::Num1), Key::A),
-        (Keycode::new(glfw::Key::Num2), Key::B),
-        (Keycode::new(glfw::Key::Num3), Key::X),
-        (Keycode::new(glfw::Key::Num4), Key::Y),
-        (Keycode::new(glfw::Key::Q), Key::L),
-        (Keycode::new(glfw::Key::E), Key::R),
-        (Keycode::new(glfw::Key::Tab), Key::Start),
-        (Keycode::new(glfw::Key::Space), Key::Select),
-        (Keycode::new(glfw::Key::GraveAccent), Key::Debug),
-    ])
+pub fn default_keybindings() -> Keybindings {
+    let kb = |kc| Input::Keyboard(Keycode::new(kc));
+    vec![
+        (kb(glfw::Key::W), Key::Up),
+        (kb(glfw::Key::S), Key::Down),
+        (kb(glfw::Key::A), Key::Left),
+        (kb(glfw::Key::D), Key::Right),
+        (kb(glfw::Key::Up), Key::Up),
+        (kb(glfw::Key::Down), Key::Down),
+        (kb(glfw::Key::Left), Key::Left),
+        (kb(glfw::Key::Right), Key::Right),
+        (kb(glfw::Key::Num1), Key::A),
+        (kb(glfw::Key::Num2), Key::B),
+        (kb(glfw::Key::Num3), Key::X),
+        (kb(glfw::Key::Num4), Key::Y),
+        (kb(glfw::Key::Q), Key::L),
+        (kb(glfw::Key::E), Key::R),
+        (kb(glfw::Key::Tab), Key::Start),
+        (kb(glfw::Key::Space), Key::Select),
+        (kb(glfw::Key::GraveAccent), Key::Debug),
+    ].into_iter().chain(default_gamepad_keybindings()).collect()
+}
+
+fn keybindings_slot(profile: &str) -> String {
+    format!("keybindings-{}", profile)
 }
 
 impl State {
@@ -241,26 +488,44 @@ impl State {
         );
         let mesh_square = mesh::Mesh::from_obj(ctx, include_bytes!("assets/meshes/square.obj"));
 
-        let nextframe = now(ctx);
+        let last_frame = now(ctx);
 
         Self {
-            // we initialize the tick to 1000, which allows us to use "0" as the default time for
-            // various animation starts on entities without having them all play at game start
-            tick: 1000,
+            tick: INITIAL_TICK,
+            tick_rate: DEFAULT_TICK_RATE,
+            last_frame,
+            accumulator: 0.0,
+            alpha: 0.0,
 
-            nextframe,
             fps: 0,
             frames_this_second: 0,
-            start_this_second: nextframe,
+            start_this_second: last_frame,
 
             rebinding: None,
+            rebinding_additional: false,
             keybindings: default_keybindings(),
+            keybinding_profile: "default".to_owned(),
+            save_id: None,
             keys: Keys::new(),
+            debug: false,
+            mouse_delta: glam::Vec2::ZERO,
+            mouse_scroll: glam::Vec2::ZERO,
+            gamepad: gamepad::Gamepad::new(),
+            rng: rand::rngs::SmallRng::from_entropy(),
+            recorder: None,
+            playback: None,
 
             screen,
             render_framebuffer,
             render_dims: glam::Vec2::new(ctx.render_width, ctx.render_height),
             postprocessing: postprocessing::Pipeline::new(ctx),
+            screenshot_request: None,
+            screen_capture: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            video: None,
+            gif: if ctx.options.contains(crate::Options::GIF_BUFFER) {
+                Some(capture::GifRecorder::new(capture::DEFAULT_GIF_SECONDS, DEFAULT_TICK_RATE))
+            } else { None },
             shader_text_bitmap,
             mesh_square,
             font_default: font::Bitmap::default(ctx),
@@ -359,6 +624,15 @@ impl State {
         )
     }
 
+    /// World-space ray through a point in render coordinates (as passed to Game::mouse_move)
+    pub fn screen_ray(&self, ctx: &context::Context, x: i32, y: i32) -> picking::Ray {
+        picking::Ray::from_screen(
+            glam::Vec2::new(ctx.render_width, ctx.render_height),
+            &self.projection, &self.view(),
+            x as f32 + 0.5, y as f32 + 0.5,
+        )
+    }
+
     pub fn bind_3d_helper(&self, ctx: &context::Context, shader: &shader::Shader, plc: usize, orth: bool) {
         shader.bind(ctx);
         shader.set_mat4(ctx, "projection", if orth { &self.projection_orth } else { &self.projection });
@@ -436,6 +710,7 @@ impl State {
         game: &mut G
     ) -> utils::Erm<()> where G: Game
     {
+        if ctx.options.contains(crate::Options::HEADLESS) { return Ok(()) }
         if self.audio.is_none() {
             self.audio = Some(audio::Assets::new(|actx| {
                 game.initialize_audio(ctx, self, actx)
@@ -444,12 +719,112 @@ impl State {
         Ok(())
     }
 
+    /// Feed an input event from the platform layer into the state.
+    /// Live input is recorded if a recording is in progress, and ignored while a replay is playing.
+    pub fn input_event<G>(&mut self, ctx: &context::Context, game: &mut G, ev: replay::Event) -> utils::Erm<()> where G: Game {
+        if self.playback.is_some() { return Ok(()) }
+        if let Some(r) = &mut self.recorder {
+            r.record(self.tick, ev.clone());
+        }
+        self.handle_event(ctx, game, ev)
+    }
+
+    fn handle_event<G>(&mut self, ctx: &context::Context, game: &mut G, ev: replay::Event) -> utils::Erm<()> where G: Game {
+        match ev {
+            replay::Event::KeyPressed(key) => { self.handle_input_pressed(Input::Keyboard(key)); Ok(()) },
+            replay::Event::KeyReleased(key) => { self.handle_input_released(Input::Keyboard(key)); Ok(()) },
+            replay::Event::GamepadPressed(b) => { self.handle_input_pressed(Input::Gamepad(b)); Ok(()) },
+            replay::Event::GamepadReleased(b) => { self.handle_input_released(Input::Gamepad(b)); Ok(()) },
+            replay::Event::VirtualKeyPressed(k) => {
+                self.keys.stick[k] = true;
+                self.press_key(k);
+                Ok(())
+            },
+            replay::Event::VirtualKeyReleased(k) => {
+                self.keys.stick[k] = false;
+                self.keys.pressed[k] = self.key_held(k);
+                Ok(())
+            },
+            replay::Event::MouseMoved(x, y) => self.handle_mouse_moved(ctx, game, x, y),
+            replay::Event::MousePressed(b) => {
+                self.initialize_audio(ctx, game)?;
+                if self.handle_input_pressed(Input::Mouse(b)) {
+                    game.mouse_button_press(ctx, self, b)?;
+                }
+                Ok(())
+            },
+            replay::Event::MouseReleased(b) => {
+                self.handle_input_released(Input::Mouse(b));
+                game.mouse_button_released(ctx, self, b)
+            },
+            replay::Event::MouseScrolled(dx, dy) => {
+                self.mouse_scroll += glam::Vec2::new(dx, dy);
+                game.mouse_scroll(ctx, self, dx, dy)
+            },
+            replay::Event::MouseDelta(dx, dy) => {
+                self.mouse_delta += glam::Vec2::new(dx, dy);
+                game.mouse_delta(ctx, self, dx, dy)
+            },
+            replay::Event::TextInput(c) => game.text_input(ctx, self, c),
+            replay::Event::TextEdit(e) => game.text_edit(ctx, self, e),
+        }
+    }
+
+    /// Update virtual keys for a newly pressed physical input.
+    /// Returns false if the input was consumed by rebinding.
+    fn handle_input_pressed(&mut self, input: Input) -> bool {
+        self.keys.held.insert(input);
+        if let Some(k) = self.rebinding.take() {
+            if !self.rebinding_additional {
+                self.keybindings.unbind_device(k, &input);
+            }
+            self.keybindings.bind(input, k);
+            self.save_keybindings();
+            false
+        } else {
+            if let Some(k) = self.keybindings.get(&input) {
+                self.press_key(k);
+            }
+            true
+        }
+    }
+
+    fn press_key(&mut self, k: Key) {
+        if k == Key::Debug && !self.keys.pressed[k] {
+            self.debug = !self.debug;
+        }
+        self.keys.pressed[k] = true;
+        self.keys.new[k] = true;
+    }
+
+    fn handle_input_released(&mut self, input: Input) {
+        self.keys.held.remove(&input);
+        if let Some(k) = self.keybindings.get(&input) {
+            self.keys.pressed[k] = self.key_held(k);
+        }
+    }
+
+    /// Is anything (a bound input or the stick) still holding the given virtual key?
+    fn key_held(&self, k: Key) -> bool {
+        self.keys.stick[k] || self.keys.held.iter().any(|i| self.keybindings.get(i) == Some(k))
+    }
+
     pub fn mouse_moved<G>(
         &mut self,
         ctx: &context::Context,
         x: f32, y: f32,
         game: &mut G
     ) -> utils::Erm<()> where G: Game
+    {
+        self.input_event(ctx, game, replay::Event::MouseMoved(x, y))
+    }
+
+    fn handle_mouse_moved<G>(
+        &mut self,
+        ctx: &context::Context,
+        game: &mut G,
+        x: f32, y: f32,
+    ) -> utils::Erm<()> where G: Game
     {
         if ctx.options.contains(crate::Options::FULLSCREEN_MOUSE) {
             game.mouse_move(ctx, self,
@@ -469,19 +844,61 @@ impl State {
     pub fn mouse_pressed<G>(
         &mut self,
         ctx: &context::Context,
-        game: &mut G
+        game: &mut G,
+        button: MouseButton,
     ) -> utils::Erm<()> where G: Game {
-        self.initialize_audio(ctx, game)?;
-        game.mouse_press(ctx, self)?;
-        Ok(())
+        self.input_event(ctx, game, replay::Event::MousePressed(button))
     }
 
     pub fn mouse_released<G>(
         &mut self,
         ctx: &context::Context,
-        game: &mut G
+        game: &mut G,
+        button: MouseButton,
+    ) -> utils::Erm<()> where G: Game {
+        self.input_event(ctx, game, replay::Event::MouseReleased(button))
+    }
+
+    pub fn mouse_scrolled<G>(
+        &mut self,
+        ctx: &context::Context,
+        game: &mut G,
+        dx: f32, dy: f32,
+    ) -> utils::Erm<()> where G: Game {
+        self.input_event(ctx, game, replay::Event::MouseScrolled(dx, dy))
+    }
+
+    /// Raw mouse motion from the platform layer, ignored unless the mouse is locked
+    pub fn mouse_motion<G>(
+        &mut self,
+        ctx: &context::Context,
+        game: &mut G,
+        dx: f32, dy: f32,
+    ) -> utils::Erm<()> where G: Game {
+        if !ctx.is_mouse_locked() { return Ok(()) }
+        self.input_event(ctx, game, replay::Event::MouseDelta(dx, dy))
+    }
+
+    pub fn text_input<G>(
+        &mut self,
+        ctx: &context::Context,
+        game: &mut G,
+        c: char,
+    ) -> utils::Erm<()> where G: Game {
+        if c.is_control() { return Ok(()) }
+        self.input_event(ctx, game, replay::Event::TextInput(c))
+    }
+
+    /// Send a text editing event if the key is one, called on both press and repeat
+    pub fn text_key<G>(
+        &mut self,
+        ctx: &context::Context,
+        game: &mut G,
+        key: Keycode,
     ) -> utils::Erm<()> where G: Game {
-        game.mouse_released(ctx, self)?;
+        if let Some(e) = TextEdit::from_keycode(key) {
+            self.input_event(ctx, game, replay::Event::TextEdit(e))?;
+        }
         Ok(())
     }
 
@@ -491,56 +908,205 @@ impl State {
         game: &mut G,
         key: Keycode,
     ) -> utils::Erm<()> where G: Game {
-        #[cfg(target_arch = "wasm32")]
-        let rebind = key.kc == winit::keyboard::KeyCode::F12;
-        #[cfg(not(target_arch = "wasm32"))]
-        let rebind = key.kc == glfw::Key::F12;
-        if rebind {
-            self.keybindings = default_keybindings();
-            self.rebinding = None;
-            game.keybindings_were_reset(ctx, self)?;
-        } else if let Some(k) = self.rebinding {
-            self.keybindings.insert(key, k);
-            self.rebinding = None;
-        } else if let Some(k) = self.keybindings.get_by_left(&key) {
-            self.keys.pressed[*k] = true;
-            self.keys.new[*k] = true;
+        if let Some(h) = self.hotkey(ctx, key) {
+            return self.handle_hotkey(ctx, game, h);
+        }
+        self.input_event(ctx, game, replay::Event::KeyPressed(key))
+    }
+
+    /// The engine hotkey for a key, if it is one and currently active.
+    /// Capture hotkeys only work in debug mode or with Options::CAPTURE_KEYS.
+    fn hotkey(&self, ctx: &context::Context, key: Keycode) -> Option<Hotkey> {
+        let capture = self.debug || ctx.options.contains(crate::Options::CAPTURE_KEYS);
+        match Hotkey::from_keycode(key)? {
+            Hotkey::ResetBindings => Some(Hotkey::ResetBindings),
+            _ if !capture => None,
+            Hotkey::Gif if self.gif.is_none() => None,
+            h => Some(h),
+        }
+    }
+
+    fn handle_hotkey<G>(&mut self, ctx: &context::Context, game: &mut G, h: Hotkey) -> utils::Erm<()> where G: Game {
+        match h {
+            Hotkey::ResetBindings => {
+                // bindings are part of what a replay depends on, so leave them alone while one is playing
+                if self.playback.is_some() { return Ok(()) }
+                self.keybindings = default_keybindings();
+                self.rebinding = None;
+                self.save_keybindings();
+                game.keybindings_were_reset(ctx, self)?;
+            },
+            Hotkey::Screenshot => self.screenshot(capture::Source::Output),
+            #[cfg(not(target_arch = "wasm32"))]
+            Hotkey::Video => if let Err(e) = self.toggle_video(capture::VideoFormat::Y4m) {
+                log::warn!("failed to toggle video recording: {}", e);
+            },
+            Hotkey::Gif => if let Err(e) = self.save_gif() {
+                log::warn!("failed to save gif: {}", e);
+            },
         }
         Ok(())
     }
 
     pub fn key_released<G>(
         &mut self,
-        _ctx: &context::Context,
-        _game: &mut G,
+        ctx: &context::Context,
+        game: &mut G,
         key: Keycode,
     ) -> utils::Erm<()> where G: Game {
-        if let Some(k) = self.keybindings.get_by_left(&key) {
-            self.keys.pressed[*k] = false;
+        if self.hotkey(ctx, key).is_some() { return Ok(()) }
+        self.input_event(ctx, game, replay::Event::KeyReleased(key))
+    }
+
+    /// Poll the gamepad and send events for any buttons or stick directions that changed.
+    /// Does nothing while a replay is playing, so that live input can't leak into it.
+    pub fn poll_gamepad<G>(&mut self, ctx: &context::Context, game: &mut G) -> utils::Erm<()> where G: Game {
+        if self.playback.is_some() { return Ok(()) }
+        let prev = self.gamepad.buttons.clone();
+        self.gamepad.poll(ctx);
+        for (b, down) in self.gamepad.buttons.clone().iter() {
+            if *down && !prev[b] {
+                self.input_event(ctx, game, replay::Event::GamepadPressed(b))?;
+            } else if !*down && prev[b] {
+                self.input_event(ctx, game, replay::Event::GamepadReleased(b))?;
+            }
+        }
+        let held = self.gamepad.stick_keys();
+        for (k, h) in held.iter() {
+            if *h && !self.gamepad.keys[k] {
+                self.input_event(ctx, game, replay::Event::VirtualKeyPressed(k))?;
+            } else if !*h && self.gamepad.keys[k] {
+                self.input_event(ctx, game, replay::Event::VirtualKeyReleased(k))?;
+            }
+        }
+        self.gamepad.keys = held;
+        Ok(())
+    }
+
+    /// Reseed the state's RNG. Games should draw randomness from `State::rng` so that replays are deterministic.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = rand::rngs::SmallRng::seed_from_u64(seed);
+    }
+
+    /// Begin recording input events (and reseed the RNG so the recording can be replayed exactly)
+    pub fn start_recording(&mut self) {
+        let seed = rand::random();
+        self.seed_rng(seed);
+        self.recorder = Some(replay::Recorder::new(self.tick, seed));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<replay::Recording> {
+        self.recorder.take().map(|r| r.finish())
+    }
+
+    /// Play back a recording from the current tick. Live input is ignored until playback finishes.
+    pub fn start_playback(&mut self, rec: replay::Recording) {
+        self.seed_rng(rec.seed);
+        self.playback = Some(replay::Playback::new(self.tick, rec));
+    }
+
+    pub fn stop_playback(&mut self) {
+        self.playback = None;
+    }
+
+    fn run_playback<G>(&mut self, ctx: &context::Context, game: &mut G) -> utils::Erm<()> where G: Game {
+        let Some(p) = &mut self.playback else { return Ok(()) };
+        for ev in p.take_due(self.tick) {
+            self.handle_event(ctx, game, ev)?;
+        }
+        if self.playback.as_ref().is_some_and(|p| p.is_finished()) {
+            self.playback = None;
         }
         Ok(())
     }
 
     /// Return the first keybinding for the given virtual key
     pub fn keybinding_for(&self, k: Key) -> Option<String> {
-        self.keybindings.get_by_right(&k).map(|kc| format!("{}", kc))
+        self.keybindings.inputs_for(k).next().map(|i| format!("{}", i))
     }
 
+    /// Return all keybindings for the given virtual key
+    pub fn keybindings_for(&self, k: Key) -> Vec<String> {
+        self.keybindings.inputs_for(k).map(|i| format!("{}", i)).collect()
+    }
+
+    /// Bind the next input to the given virtual key, replacing its bindings from the same device
     pub fn rebind_key(&mut self, k: Key) {
         self.rebinding = Some(k);
+        self.rebinding_additional = false;
+    }
+
+    /// Bind the next input to the given virtual key, keeping its existing bindings
+    pub fn add_keybinding(&mut self, k: Key) {
+        self.rebinding = Some(k);
+        self.rebinding_additional = true;
+    }
+
+    /// Load the default keybinding profile if the game has a save id (see Game::save_id)
+    pub fn initialize_keybindings<G>(&mut self, ctx: &context::Context, game: &G) where G: Game {
+        if ctx.options.contains(crate::Options::HEADLESS) { return }
+        if let Some(id) = game.save_id() {
+            self.load_keybindings(id, "default");
+        }
+    }
+
+    /// Load the named keybinding profile for this game, and save it whenever bindings change
+    pub fn load_keybindings(&mut self, id: &str, profile: &str) {
+        self.save_id = Some(id.to_owned());
+        self.keybinding_profile = profile.to_owned();
+        self.keybindings = save::load_slot(id, &keybindings_slot(profile))
+            .unwrap_or_else(default_keybindings);
+    }
+
+    pub fn save_keybindings(&self) {
+        if let Some(id) = &self.save_id
+            && let Err(e) = save::save_slot(id, &keybindings_slot(&self.keybinding_profile), &self.keybindings) {
+                log::warn!("failed to save keybindings: {}", e);
+            }
+    }
+
+    /// Seconds of simulated time per tick
+    pub fn delta_time(&self) -> f64 {
+        1.0 / self.tick_rate
+    }
+
+    /// Change the number of updates per second (e.g. to run the simulation at 120Hz)
+    pub fn set_tick_rate(&mut self, hz: f64) {
+        self.tick_rate = hz.max(1.0);
+        self.accumulator = self.accumulator.min(self.delta_time());
+        if let Some(g) = &self.gif { // frame timing depends on the tick rate, so start over
+            self.enable_gif_buffer(g.seconds);
+        }
+    }
+
+    /// Advance the simulation by exactly one tick, regardless of wall-clock time
+    pub fn run_tick<G>(&mut self, ctx: &context::Context, game: &mut G) -> utils::Erm<()> where G: Game {
+        self.run_playback(ctx, game)?;
+        self.tick += 1;
+        self.frames_this_second += 1;
+        game.update(ctx, self)?;
+        self.keys.new = enum_map! { _ => false };
+        self.mouse_delta = glam::Vec2::ZERO;
+        self.mouse_scroll = glam::Vec2::ZERO;
+        Ok(())
     }
 
     pub fn run_update<G>(&mut self, ctx: &context::Context, game: &mut G) -> utils::Erm<()> where G: Game {
         let now = now(ctx);
-        if now > self.nextframe {
-            while self.nextframe < now { // find the next target frame that isn't in the past
-                self.nextframe += DELTA_TIME;
+        self.accumulator += (now - self.last_frame).max(0.0);
+        self.last_frame = now;
+        let dt = self.delta_time();
+        let mut updates = 0;
+        while self.accumulator >= dt {
+            if updates >= MAX_UPDATES_PER_FRAME { // we're too far behind to catch up, drop the backlog
+                self.accumulator %= dt;
+                break;
             }
-            self.tick += 1;
-            self.frames_this_second += 1;
-            game.update(ctx, self)?;
-            self.keys.new = enum_map! { _ => false };
+            self.accumulator -= dt;
+            self.run_tick(ctx, game)?;
+            updates += 1;
         }
+        self.alpha = (self.accumulator / dt) as f32;
         if now - self.start_this_second > 1.0 { // track FPS
             self.start_this_second = now;
             self.fps = self.frames_this_second;
@@ -549,10 +1115,115 @@ impl State {
         Ok(())
     }
 
+    /// Take a screenshot at the end of the next rendered frame and save it as a PNG
+    pub fn screenshot(&mut self, source: capture::Source) {
+        self.screenshot_request = Some(source);
+    }
+
+    /// Identifier used for data directories (the save id if set)
+    pub fn data_id(&self) -> &str {
+        self.save_id.as_deref().unwrap_or("teleia")
+    }
+
+    fn save_screenshot(&mut self, ctx: &context::Context, source: capture::Source) {
+        let img = match source {
+            capture::Source::Native => capture::Image::from_framebuffer(ctx, &self.render_framebuffer),
+            capture::Source::Output => {
+                // the screen can't be read back directly, so copy it to a framebuffer that can
+                let dims = self.screen.dims;
+                let fb = match self.screen_capture.take() {
+                    Some(fb) if fb.dims == dims => fb,
+                    _ => framebuffer::Framebuffer::new(ctx, &dims, &glam::Vec2::ZERO),
+                };
+                self.screen.blit(ctx, &fb, &glam::Vec2::ZERO, &dims);
+                let img = capture::Image::from_framebuffer(ctx, &fb);
+                self.screen_capture = Some(fb);
+                img
+            },
+        };
+        let res: utils::Erm<()> = try {
+            let png = img.to_png()?;
+            capture::export(self.data_id(), &capture::timestamped_name("screenshot", "png"), "image/png", &png)?;
+        };
+        if let Err(e) = res {
+            log::warn!("failed to save screenshot: {}", e);
+        }
+    }
+
+    /// Start recording every tick's frame of the render framebuffer to the captures directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_video(&mut self, format: capture::VideoFormat) -> utils::Erm<()> {
+        self.stop_video()?;
+        let name = match format {
+            capture::VideoFormat::Y4m => capture::timestamped_name("video", "y4m"),
+            capture::VideoFormat::PngSequence => capture::timestamped_name("video", "frames"),
+        };
+        let path = capture::captures_dir(self.data_id())?.join(name);
+        self.video = Some(capture::VideoRecorder::start(
+            path, format,
+            self.render_framebuffer.dims.x as _, self.render_framebuffer.dims.y as _,
+            self.tick_rate.round() as _,
+        )?);
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_video(&mut self) -> utils::Erm<()> {
+        if let Some(v) = self.video.take() { v.finish()?; }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn toggle_video(&mut self, format: capture::VideoFormat) -> utils::Erm<()> {
+        if self.video.is_some() { self.stop_video() } else { self.start_video(format) }
+    }
+
+    /// Keep the last few seconds of frames so they can be saved with save_gif
+    pub fn enable_gif_buffer(&mut self, seconds: f64) {
+        self.gif = Some(capture::GifRecorder::new(seconds, self.tick_rate));
+    }
+
+    pub fn disable_gif_buffer(&mut self) {
+        self.gif = None;
+    }
+
+    /// Write the buffered frames to an animated GIF in the captures directory (or download it on the web).
+    /// The buffer starts over afterwards. On native, encoding happens on a background thread.
+    pub fn save_gif(&mut self) -> utils::Erm<()> {
+        let tick_rate = self.tick_rate;
+        let Some(gif) = &mut self.gif else {
+            return utils::erm_msg("gif buffer is not enabled");
+        };
+        let clip = gif.take_clip(tick_rate);
+        let id = self.data_id().to_owned();
+        let name = capture::timestamped_name("clip", "gif");
+        let write = move || -> utils::Erm<()> {
+            capture::export(&id, &name, "image/gif", &clip.encode()?)
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || if let Err(e) = write() {
+            log::warn!("failed to save gif: {}", e);
+        });
+        #[cfg(target_arch = "wasm32")]
+        write()?;
+        Ok(())
+    }
+
     pub fn run_render<G>(&mut self, ctx: &context::Context, game: &mut G) -> utils::Erm<()> where G: Game {
+        let screenshot = self.screenshot_request.take();
         self.bind_render_framebuffer(ctx);
 
-        game.render(ctx, self)?;
+        game.render_interpolated(ctx, self, self.alpha)?;
+        if screenshot == Some(capture::Source::Native) {
+            self.save_screenshot(ctx, capture::Source::Native);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(v) = &mut self.video {
+            v.capture(ctx, &self.render_framebuffer, self.tick);
+        }
+        if let Some(g) = &mut self.gif {
+            g.capture(ctx, &self.render_framebuffer, self.tick);
+        }
 
         self.bind_screen(ctx);
         ctx.clear_color(
@@ -565,6 +1236,9 @@ impl State {
         ctx.clear();
         self.postprocessing.render(ctx, self)?;
         self.postprocessing.finish();
+        if screenshot == Some(capture::Source::Output) {
+            self.save_screenshot(ctx, capture::Source::Output);
+        }
         #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
         {
             let err = unsafe { ctx.gl.get_error() };
@@ -1,4 +1,4 @@
-use crate::{state, utils};
+use crate::{context, font, state, utils};
 use crate::state::Tick;
 
 fn compute_reverse(frames: Tick, tick: Tick, start: Tick) -> Tick {
@@ -167,3 +167,147 @@ impl Cursor {
         } else { false }
     }
 }
+
+/// A single line of editable text, fed from Game::text_input and Game::text_edit
+pub struct TextField {
+    pub text: String,
+    /// Position of the cursor, in characters
+    pub cursor: usize,
+    pub max_len: usize,
+}
+
+impl TextField {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+            max_len,
+        }
+    }
+
+    fn byte_index(&self, cidx: usize) -> usize {
+        self.text.char_indices().nth(cidx).map(|(i, _)| i).unwrap_or(self.text.len())
+    }
+
+    pub fn len(&self) -> usize { self.text.chars().count() }
+    pub fn is_empty(&self) -> bool { self.text.is_empty() }
+
+    pub fn set(&mut self, text: &str) {
+        self.text = text.chars().take(self.max_len).collect();
+        self.cursor = self.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Insert a character at the cursor. Returns false if the field is full.
+    pub fn input(&mut self, c: char) -> bool {
+        if self.len() >= self.max_len { return false; }
+        let idx = self.byte_index(self.cursor);
+        self.text.insert(idx, c);
+        self.cursor += 1;
+        true
+    }
+
+    /// Apply an editing key. Returns true if Enter was pressed (i.e. the text was submitted).
+    pub fn edit(&mut self, e: state::TextEdit) -> bool {
+        match e {
+            state::TextEdit::Backspace => if self.cursor > 0 {
+                self.cursor -= 1;
+                let idx = self.byte_index(self.cursor);
+                self.text.remove(idx);
+            },
+            state::TextEdit::Delete => if self.cursor < self.len() {
+                let idx = self.byte_index(self.cursor);
+                self.text.remove(idx);
+            },
+            state::TextEdit::Left => self.cursor = self.cursor.saturating_sub(1),
+            state::TextEdit::Right => self.cursor = (self.cursor + 1).min(self.len()),
+            state::TextEdit::Home => self.cursor = 0,
+            state::TextEdit::End => self.cursor = self.len(),
+            state::TextEdit::Enter => return true,
+        }
+        false
+    }
+
+    /// Draw the text with a blinking cursor underneath, positioned as in Bitmap::render_text_at
+    pub fn render(&self,
+        ctx: &context::Context, st: &mut state::State,
+        font: &font::Bitmap,
+        pos: glam::Vec2,
+        params: font::BitmapParams,
+    ) {
+        let (scale, offset, color) = (params.scale, params.offset, params.color);
+        font.render_text_at(ctx, st, pos, &self.text, params);
+        // blink once a second, whatever the tick rate
+        if (st.tick as f64 * 2.0 / st.tick_rate) as u64 % 2 == 0 {
+            let cursor = format!("{}_", " ".repeat(self.cursor));
+            font.render_text_at(ctx, st, pos, &cursor, font::BitmapParams {
+                color: &color[..color.len().min(1)],
+                scale,
+                offset,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(max_len: usize, s: &str) -> TextField {
+        let mut f = TextField::new(max_len);
+        for c in s.chars() { f.input(c); }
+        f
+    }
+
+    #[test]
+    fn input_respects_max_len() {
+        let mut f = typed(3, "ab");
+        assert!(f.input('c'));
+        assert!(!f.input('d'));
+        assert_eq!(f.text, "abc");
+        assert_eq!(f.cursor, 3);
+    }
+
+    #[test]
+    fn editing_at_the_cursor() {
+        let mut f = typed(10, "héllo");
+        f.edit(state::TextEdit::Home);
+        f.edit(state::TextEdit::Right);
+        f.edit(state::TextEdit::Delete);
+        assert_eq!(f.text, "hllo");
+        f.input('e');
+        assert_eq!(f.text, "hello");
+        f.edit(state::TextEdit::End);
+        f.edit(state::TextEdit::Backspace);
+        assert_eq!(f.text, "hell");
+        assert_eq!(f.cursor, 4);
+    }
+
+    #[test]
+    fn cursor_stays_in_bounds() {
+        let mut f = typed(10, "ab");
+        f.edit(state::TextEdit::Right);
+        assert_eq!(f.cursor, 2);
+        f.edit(state::TextEdit::Delete);
+        assert_eq!(f.text, "ab");
+        f.edit(state::TextEdit::Home);
+        f.edit(state::TextEdit::Left);
+        f.edit(state::TextEdit::Backspace);
+        assert_eq!((f.text.as_str(), f.cursor), ("ab", 0));
+    }
+
+    #[test]
+    fn enter_submits_and_set_truncates() {
+        let mut f = TextField::new(4);
+        assert!(f.edit(state::TextEdit::Enter));
+        assert!(!f.edit(state::TextEdit::Left));
+        f.set("abcdef");
+        assert_eq!((f.text.as_str(), f.cursor), ("abcd", 4));
+        f.clear();
+        assert!(f.is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+// Runs games on the offscreen context from run_headless, so no GPU or window system is needed.
+// Everything is in one test since run_headless sets process-wide globals and initializes GLFW.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use rand::Rng;
+use teleia::{context, replay, state, Erm, Options};
+
+#[derive(Default)]
+struct Counter {
+    renders: u64,
+    /// Ticks (counted from the first update) on which Up was held
+    up: Vec<teleia::Tick>,
+    /// One number drawn from the state's RNG per tick
+    rolls: Vec<u32>,
+}
+impl state::Game for Counter {
+    fn update(&mut self, _ctx: &context::Context, st: &mut state::State) -> Erm<()> {
+        if st.keys.up() { self.up.push(elapsed(st)); }
+        self.rolls.push(st.rng.gen_range(0..1000));
+        Ok(())
+    }
+    fn render(&mut self, _ctx: &context::Context, _st: &mut state::State) -> Erm<()> {
+        self.renders += 1;
+        Ok(())
+    }
+}
+
+/// Ticks since run_headless started
+fn elapsed(st: &state::State) -> teleia::Tick { st.tick - state::INITIAL_TICK }
+
+fn new_counter(_ctx: &context::Context, _st: &mut state::State) -> Counter { Counter::default() }
+
+/// Hold Up from the 4th tick through the 5th
+fn press_up(ctx: &context::Context, st: &mut state::State, game: &mut Counter) -> Erm<()> {
+    match elapsed(st) {
+        3 => st.input_event(ctx, game, replay::Event::VirtualKeyPressed(state::Key::Up)),
+        5 => st.input_event(ctx, game, replay::Event::VirtualKeyReleased(state::Key::Up)),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn headless() -> Erm<()> {
+    let (game, st) = teleia::run_headless(64, 64, Options::empty(), 10, true, new_counter, press_up)?;
+    assert_eq!(elapsed(&st), 10);
+    assert_eq!(game.rolls.len(), 10);
+    assert_eq!(game.renders, 10);
+    assert_eq!(game.up, vec![4, 5]);
+
+    let (_, st) = teleia::run_headless(64, 64, Options::empty(), 3, false, new_counter, |_, _, _| Ok(()))?;
+    assert_eq!(elapsed(&st), 3);
+
+    // a recording played back gives the same input and the same random numbers, ignoring live input
+    let (recorded, mut st) = teleia::run_headless(64, 64, Options::empty(), 8, false, new_counter, |ctx, st, game| {
+        if elapsed(st) == 0 { st.start_recording(); }
+        press_up(ctx, st, game)
+    })?;
+    let recording = st.stop_recording().expect("recording was started");
+    let (replayed, _) = teleia::run_headless(64, 64, Options::empty(), 8, false, new_counter, move |ctx, st, game| {
+        match elapsed(st) {
+            0 => { st.start_playback(recording.clone()); Ok(()) },
+            1 => st.input_event(ctx, game, replay::Event::VirtualKeyPressed(state::Key::Up)),
+            _ => Ok(()),
+        }
+    })?;
+    assert_eq!(recorded.up, vec![4, 5]);
+    assert_eq!(replayed.up, recorded.up);
+    assert_eq!(replayed.rolls, recorded.rolls);
+    Ok(())
+}